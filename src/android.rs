@@ -0,0 +1,356 @@
+//! Android specific options of message
+//!
+//! Reference: https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages#androidconfig
+
+use std::time::Duration;
+
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Priority;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AndroidConfig {
+    /// identifier of a group of messages that can be collapsed, so that only the last message gets sent when delivery can be resumed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collapse_key: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<AndroidMessagePriority>,
+
+    /// how long the message should be kept in storage if the device is offline, up to 4 weeks
+    #[serde(
+        serialize_with = "serialize_option_ttl",
+        deserialize_with = "deserialize_option_ttl",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ttl: Option<Duration>,
+
+    /// package name of the application where the registration token must match in order to receive the message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restricted_package_name: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification: Option<AndroidNotification>,
+}
+
+/// Reference: https://firebase.google.com/docs/cloud-messaging/android/message-priority
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum AndroidMessagePriority {
+    /// may be delayed while device is dozing, to save battery
+    Normal,
+    /// delivered immediately and wakes dozing device, for time sensitive messages
+    High,
+}
+
+/// same as apns, only [`Priority::High`] wakes device
+impl From<Priority> for AndroidMessagePriority {
+    fn from(priority: Priority) -> Self {
+        match priority {
+            Priority::Low | Priority::Normal => Self::Normal,
+            Priority::High => Self::High,
+        }
+    }
+}
+
+/// fields which are not set fall back to top level notification of message
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AndroidNotification {
+    /// overrides `Message`'s title on android
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    /// overrides `Message`'s body on android
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+
+    /// required on android 8+ to display notification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<String>,
+
+    /// drawable resource name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+
+    /// `#rrggbb` format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+
+    /// not set means silent, unless `default_sound` is true or channel has its own sound
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound: Option<AndroidSound>,
+
+    /// notifications with same tag replace each other in notification drawer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+
+    /// activity with a matching intent filter is launched when user clicks notification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub click_action: Option<String>,
+
+    /// image url, overrides `Message`'s image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+
+    /// if true, default sound of android framework is used and `sound` is ignored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_sound: Option<bool>,
+
+    /// if true, default vibration pattern of android framework is used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_vibrate_timings: Option<bool>,
+
+    /// led color and blink rate, on devices that have a notification led
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub light_settings: Option<LightSettings>,
+
+    /// position of notification in notification drawer, not to be confused with [`AndroidMessagePriority`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification_priority: Option<NotificationPriority>,
+
+    /// key of title string in app resources, filled from `Message` if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title_loc_key: Option<String>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub title_loc_args: Vec<String>,
+
+    /// key of body string in app resources, filled from `Message` if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_loc_key: Option<String>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub body_loc_args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightSettings {
+    pub color: Color,
+
+    #[serde(serialize_with = "serialize_ttl", deserialize_with = "deserialize_ttl")]
+    pub light_on_duration: Duration,
+
+    #[serde(serialize_with = "serialize_ttl", deserialize_with = "deserialize_ttl")]
+    pub light_off_duration: Duration,
+}
+
+/// rgba color, each component in `0.0..=1.0`
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct Color {
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+
+    /// fully opaque if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alpha: Option<f32>,
+}
+
+/// only applies to android 7.1 and lower, importance of channel is used on android 8+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum NotificationPriority {
+    /// shown only in expanded notification drawer, without status bar icon
+    PriorityMin,
+    /// no heads up display and no sound interruption, e.g. for chatty updates
+    PriorityLow,
+    PriorityDefault,
+    /// may be shown as heads up notification
+    PriorityHigh,
+    /// for time critical notifications, e.g. incoming call
+    PriorityMax,
+}
+
+/// Reference: https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages#androidnotification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AndroidSound {
+    /// default sound of android framework
+    Default,
+    /// sound resource name in `/res/raw/`, without extension
+    Named(String),
+}
+
+impl Serialize for AndroidSound {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Self::Default => serializer.serialize_str("default"),
+            Self::Named(name) => serializer.serialize_str(name),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for AndroidSound {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let name = String::deserialize(deserializer)?;
+
+        Ok(match name.as_str() {
+            "default" => Self::Default,
+            _ => Self::Named(name),
+        })
+    }
+}
+
+fn serialize_option_ttl<S>(ttl: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serialize_ttl(&ttl.unwrap_or_default(), serializer)
+}
+
+/// serializes duration in `google.protobuf.Duration` json format, e.g. `3600s` or `3.5s`
+///
+/// for use with `#[serde(serialize_with = "fcm_sdk::serialize_ttl")]`
+pub fn serialize_ttl<S>(ttl: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let s = match ttl.subsec_nanos() {
+        0 => format!("{}s", ttl.as_secs()),
+        nanos => format!("{}.{:09}s", ttl.as_secs(), nanos),
+    };
+
+    serializer.serialize_str(&s)
+}
+
+fn deserialize_option_ttl<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|x| parse_ttl(&x).ok_or_else(|| D::Error::custom(format!("invalid ttl: {x}"))))
+        .transpose()
+}
+
+/// counterpart of [`serialize_ttl`]
+pub fn deserialize_ttl<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+
+    parse_ttl(&s).ok_or_else(|| D::Error::custom(format!("invalid ttl: {s}")))
+}
+
+fn parse_ttl(s: &str) -> Option<Duration> {
+    let s = s.strip_suffix('s')?;
+
+    let (secs, nanos) = match s.split_once('.') {
+        Some((secs, frac)) if !frac.is_empty() && frac.len() <= 9 => {
+            let nanos: u32 = frac.parse().ok()?;
+            (secs, nanos * 10u32.pow(9 - frac.len() as u32))
+        }
+        Some(_) => return None,
+        None => (s, 0),
+    };
+
+    Some(Duration::new(secs.parse().ok()?, nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde::Serialize;
+
+    use super::{
+        parse_ttl, serialize_ttl, AndroidMessagePriority, AndroidNotification, AndroidSound,
+        NotificationPriority,
+    };
+    use crate::Priority;
+
+    #[test]
+    fn test_android_message_priority() {
+        assert_eq!(
+            serde_json::to_string(&AndroidMessagePriority::Normal).unwrap(),
+            r#""NORMAL""#
+        );
+        assert_eq!(
+            serde_json::to_string(&AndroidMessagePriority::High).unwrap(),
+            r#""HIGH""#
+        );
+
+        assert_eq!(
+            AndroidMessagePriority::from(Priority::Low),
+            AndroidMessagePriority::Normal
+        );
+        assert_eq!(
+            AndroidMessagePriority::from(Priority::High),
+            AndroidMessagePriority::High
+        );
+    }
+
+    #[test]
+    fn test_quiet_notification() {
+        let notification = AndroidNotification {
+            notification_priority: NotificationPriority::PriorityLow.into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            serde_json::json!({ "notification_priority": "PRIORITY_LOW" })
+        );
+
+        let notification = AndroidNotification {
+            sound: AndroidSound::Named("chime".to_string()).into(),
+            notification_priority: NotificationPriority::PriorityMin.into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            serde_json::json!({ "sound": "chime", "notification_priority": "PRIORITY_MIN" })
+        );
+    }
+
+    #[test]
+    fn test_android_sound() {
+        assert_eq!(
+            serde_json::to_string(&AndroidSound::Default).unwrap(),
+            r#""default""#
+        );
+
+        let actual: AndroidSound = serde_json::from_str(r#""default""#).unwrap();
+        assert_eq!(actual, AndroidSound::Default);
+
+        let actual: AndroidSound = serde_json::from_str(r#""chime""#).unwrap();
+        assert_eq!(actual, AndroidSound::Named("chime".to_string()));
+    }
+
+    #[test]
+    fn test_serialize_ttl() {
+        #[derive(Serialize)]
+        struct X {
+            #[serde(serialize_with = "serialize_ttl")]
+            ttl: Duration,
+        }
+
+        let actual = |ttl| serde_json::to_string(&X { ttl }).unwrap();
+
+        assert_eq!(actual(Duration::ZERO), r#"{"ttl":"0s"}"#);
+        assert_eq!(actual(Duration::from_secs(3600)), r#"{"ttl":"3600s"}"#);
+        assert_eq!(
+            actual(Duration::from_millis(3500)),
+            r#"{"ttl":"3.500000000s"}"#
+        );
+        assert_eq!(actual(Duration::from_nanos(1)), r#"{"ttl":"0.000000001s"}"#);
+    }
+
+    #[test]
+    fn test_parse_ttl() {
+        assert_eq!(parse_ttl("3600s"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_ttl("3.5s"), Some(Duration::from_millis(3500)));
+        assert_eq!(parse_ttl("0.000000001s"), Some(Duration::from_nanos(1)));
+        assert_eq!(parse_ttl("3600"), None);
+        assert_eq!(parse_ttl("3.s"), None);
+        assert_eq!(parse_ttl("-1s"), None);
+    }
+}
@@ -0,0 +1,156 @@
+//! Synchronous client, enabled by `blocking` feature
+//!
+//! for programs without async runtime, e.g. cli tools sending a single notification,
+//! must not be used inside async runtime
+
+use std::{borrow::Borrow, path::Path};
+
+use serde::Serialize;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{Credential, GoogleOAuth2, Message, SendMessageSuccessResponse, SendOptions};
+
+/// blocking counterpart of [`crate::FirebaseCloudMessaging`], which runs it on own single threaded runtime
+///
+/// so retry policy, hooks and token refresh behave the same as async client
+pub struct FirebaseCloudMessaging {
+    inner: crate::FirebaseCloudMessaging,
+    runtime: Runtime,
+}
+
+impl FirebaseCloudMessaging {
+    pub fn from_credential_path<P>(p: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_credential(Credential::from_path(p))
+    }
+
+    pub fn from_env() -> Self {
+        Self::from_credential(Credential::from_env())
+    }
+
+    /// see [`crate::FirebaseCloudMessaging::from_credential`]
+    pub fn from_credential(cred: Credential) -> Self {
+        Self::new(crate::FirebaseCloudMessaging::from_credential(cred))
+    }
+
+    /// e.g. built by [`crate::FirebaseCloudMessaging::builder`], or configured with retry policy and hooks
+    pub fn new(inner: crate::FirebaseCloudMessaging) -> Self {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed build runtime");
+
+        Self { inner, runtime }
+    }
+
+    pub fn project_id(&self) -> &str {
+        self.inner.project_id()
+    }
+
+    /// see [`crate::FirebaseCloudMessaging::with_project_id`]
+    pub fn with_project_id(mut self, project_id: impl Into<String>) -> Self {
+        self.inner = self.inner.with_project_id(project_id);
+        self
+    }
+
+    /// see [`crate::FirebaseCloudMessaging::with_base_url`]
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.inner = self.inner.with_base_url(base_url);
+        self
+    }
+
+    /// see [`crate::FirebaseCloudMessaging::with_oauth2`]
+    pub fn with_oauth2(mut self, oauth2: GoogleOAuth2) -> Self {
+        self.inner = self.inner.with_oauth2(oauth2);
+        self
+    }
+
+    /// see [`crate::FirebaseCloudMessaging::send`]
+    pub fn send<D>(
+        &self,
+        registration_token: impl Into<String>,
+        message: impl Borrow<Message>,
+        options: SendOptions,
+        data: Option<D>,
+    ) -> crate::Result<SendMessageSuccessResponse>
+    where
+        D: Serialize,
+    {
+        self.runtime
+            .block_on(self.inner.send(registration_token, message, options, data))
+    }
+
+    /// see [`crate::FirebaseCloudMessaging::send_to_topic`]
+    pub fn send_to_topic<D>(
+        &self,
+        topic: impl Into<String>,
+        message: impl Borrow<Message>,
+        options: SendOptions,
+        data: Option<D>,
+    ) -> crate::Result<SendMessageSuccessResponse>
+    where
+        D: Serialize,
+    {
+        self.runtime
+            .block_on(self.inner.send_to_topic(topic, message, options, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::FirebaseCloudMessaging;
+    use crate::{mock, Message, RetryPolicy, SendEvent, SendOptions};
+
+    #[tokio::test]
+    async fn test_send() {
+        let base_url = mock::serve(vec![
+            mock::token_response("token"),
+            (
+                503,
+                r#"{"error":{"code":503,"message":"The service is currently unavailable.","status":"UNAVAILABLE"}}"#.to_string(),
+            ),
+            (
+                200,
+                r#"{"name":"projects/project_id/messages/1"}"#.to_string(),
+            ),
+        ])
+        .await;
+
+        let events = Arc::new(parking_lot::Mutex::new(Vec::new()));
+
+        let inner = crate::FirebaseCloudMessaging::from_credential(mock::credential())
+            .with_retry_policy(RetryPolicy {
+                max_retries: 1,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(1),
+            })
+            .on_send(Box::new({
+                let events = events.clone();
+                move |x: &SendEvent| events.lock().push(x.success)
+            }));
+
+        // blocking client must not be used on async runtime thread
+        let actual = tokio::task::spawn_blocking(move || {
+            let fcm = FirebaseCloudMessaging::new(inner)
+                .with_base_url(&base_url)
+                .with_oauth2(mock::oauth2(&base_url));
+
+            fcm.send(
+                "token",
+                Message::new("title", "body"),
+                SendOptions::default(),
+                None::<()>,
+            )
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(actual.message_id(), "1");
+        assert_eq!(*events.lock(), [true]);
+    }
+}
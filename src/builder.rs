@@ -0,0 +1,149 @@
+//! Builder of [`FirebaseCloudMessaging`] for configuring underlying http client
+
+use std::time::Duration;
+
+use reqwest::{Client, Proxy};
+
+use crate::{oauth::Credential, FirebaseCloudMessaging};
+
+/// e.g. `fcm-rust/0.1.0`
+pub(crate) const USER_AGENT: &str = concat!("fcm-rust/", env!("CARGO_PKG_VERSION"));
+
+pub struct FirebaseCloudMessagingBuilder {
+    cred: Credential,
+    client: Option<Client>,
+    timeout: Duration,
+    proxies: Vec<Proxy>,
+    pool_max_idle_per_host: Option<usize>,
+    pool_idle_timeout: Option<Option<Duration>>,
+    http2_keep_alive_interval: Option<Duration>,
+    http2_keep_alive_while_idle: bool,
+    user_agent: String,
+}
+
+impl FirebaseCloudMessagingBuilder {
+    pub fn new(cred: Credential) -> Self {
+        Self {
+            cred,
+            client: None,
+            timeout: Duration::from_secs(30),
+            proxies: Vec::new(),
+            pool_max_idle_per_host: None,
+            pool_idle_timeout: None,
+            http2_keep_alive_interval: Some(Duration::from_secs(30)),
+            http2_keep_alive_while_idle: true,
+            user_agent: USER_AGENT.to_string(),
+        }
+    }
+
+    /// if set, other http client options of builder are ignored and options of `client` are used instead
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// total timeout of each request, default is 30 seconds
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// both of sending messages and fetching oauth2 token go through the proxy
+    ///
+    /// can be called multiple times, e.g. for http and https proxies
+    pub fn with_proxy(mut self, proxy: Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// maximum idle connections kept per host, default is unlimited
+    ///
+    /// idle connections are reused, which matters when sending many messages
+    pub fn with_pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// how long idle connections are kept, default is 90 seconds, `None` keeps them forever
+    pub fn with_pool_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// interval of http2 ping frames which keep the connection alive, default is 30 seconds, `None` disables it
+    ///
+    /// fcm multiplexes requests over a single http2 connection, so keeping it alive avoids reconnecting between batches
+    pub fn with_http2_keep_alive_interval(mut self, interval: Option<Duration>) -> Self {
+        self.http2_keep_alive_interval = interval;
+        self
+    }
+
+    /// whether ping frames are sent while no request is in flight, default is true
+    pub fn with_http2_keep_alive_while_idle(mut self, enabled: bool) -> Self {
+        self.http2_keep_alive_while_idle = enabled;
+        self
+    }
+
+    /// `User-Agent` header of each request, default is `fcm-rust/{version}`
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// panics if http client can't be built, e.g. `User-Agent` is not a valid header value, see [`Self::try_build`]
+    pub fn build(self) -> FirebaseCloudMessaging {
+        self.try_build().expect("failed build http client")
+    }
+
+    /// returns [`crate::Error::Reqwest`] if http client can't be built, e.g. `User-Agent` contains a newline
+    pub fn try_build(self) -> crate::Result<FirebaseCloudMessaging> {
+        let client = match self.client {
+            Some(client) => client,
+            None => {
+                let mut builder = self
+                    .proxies
+                    .into_iter()
+                    .fold(Client::builder(), |builder, proxy| builder.proxy(proxy))
+                    .timeout(self.timeout)
+                    .user_agent(self.user_agent)
+                    .http2_keep_alive_interval(self.http2_keep_alive_interval)
+                    .http2_keep_alive_while_idle(self.http2_keep_alive_while_idle);
+
+                if let Some(max) = self.pool_max_idle_per_host {
+                    builder = builder.pool_max_idle_per_host(max);
+                }
+                if let Some(timeout) = self.pool_idle_timeout {
+                    builder = builder.pool_idle_timeout(timeout);
+                }
+
+                builder.build()?
+            }
+        };
+
+        Ok(FirebaseCloudMessaging::from_credential_with_client(
+            self.cred, client,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{mock, Error, ErrorKind, FirebaseCloudMessaging};
+
+    #[test]
+    fn test_try_build() {
+        assert!(FirebaseCloudMessaging::builder(mock::credential())
+            .with_user_agent("app/1.0")
+            .try_build()
+            .is_ok());
+
+        let err = FirebaseCloudMessaging::builder(mock::credential())
+            .with_user_agent("app/1.0\nX-Injected: 1")
+            .try_build()
+            .err()
+            .unwrap();
+
+        assert!(matches!(err, Error::Reqwest(_)));
+        assert_eq!(err.kind(), ErrorKind::Reqwest);
+    }
+}
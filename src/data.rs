@@ -0,0 +1,65 @@
+//! Typed `data` of message, which fcm accepts only as flat map of string to string
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// unlike arbitrary `D: Serialize`, can't be serialized to nested or non-string values,
+/// which fcm rejects with `INVALID_ARGUMENT`
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DataPayload(HashMap<String, String>);
+
+impl DataPayload {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// returns previous value of `key`, if any
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        self.0.insert(key.into(), value.into())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<HashMap<String, String>> for DataPayload {
+    fn from(data: HashMap<String, String>) -> Self {
+        Self(data)
+    }
+}
+
+impl From<DataPayload> for HashMap<String, String> {
+    fn from(data: DataPayload) -> Self {
+        data.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::DataPayload;
+
+    #[test]
+    fn test_serialize() {
+        let mut data = DataPayload::new();
+        assert_eq!(data.insert("id", "1"), None);
+        assert_eq!(data.insert("id", "2"), Some("1".to_string()));
+
+        assert_eq!(data.get("id"), Some("2"));
+        assert_eq!(serde_json::to_string(&data).unwrap(), r#"{"id":"2"}"#);
+
+        let data = DataPayload::from(HashMap::from([("id".to_string(), "3".to_string())]));
+        assert_eq!(
+            serde_json::from_str::<DataPayload>(r#"{"id":"3"}"#).unwrap(),
+            data
+        );
+    }
+}
@@ -1,13 +1,218 @@
-use crate::fcm::SendMessageErrorResponse;
+use std::time::Duration;
+
+use http::StatusCode;
+
+use crate::{fcm::SendMessageErrorResponse, FcmErrorCode};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[error("Response Deserialize: {0}")]
-    ResponseDeserialize(serde_json::Error),
+    #[error("Response Deserialize: {source}")]
+    ResponseDeserialize {
+        source: serde_json::Error,
+        /// raw response body which failed to deserialize
+        body: String,
+    },
+
+    #[error("Request Serialize: {0}")]
+    RequestSerialize(serde_json::Error),
+
+    /// serialized message doesn't fit [`crate::FcmMessage`], see [`crate::FcmMessage::from_parts`]
+    #[error("Message Deserialize: {0}")]
+    MessageDeserialize(serde_json::Error),
+
+    /// `data` of message must be a flat map of string to string
+    #[error("Invalid Data: {0}")]
+    InvalidData(String),
+
+    /// registration token or notification key is malformed, e.g. empty, detected before sending
+    #[error("Invalid Token: {0}")]
+    InvalidToken(String),
+
+    /// topic management request has more tokens than the api accepts at once
+    #[error("Too Many Tokens: {0}, at most 1000 per request")]
+    TooManyTokens(usize),
+
+    /// header given to [`crate::FirebaseCloudMessaging::send_with_headers`] is set by the client itself, e.g. `Authorization`
+    #[error("Reserved Header: {0}")]
+    ReservedHeader(String),
 
     #[error("Reqwest: {0}")]
     Reqwest(#[from] reqwest::Error),
 
-    #[error("Send Message: {0}")]
-    SendMessage(SendMessageErrorResponse),
+    #[error("Send Message: {response}")]
+    SendMessage {
+        status: StatusCode,
+        response: SendMessageErrorResponse,
+        /// `Retry-After` header of response, if any
+        retry_after: Option<Duration>,
+    },
+
+    /// token endpoint rejected exchange, e.g. invalid key, clock skew or revoked service account
+    #[error("Token Exchange: {status} {body}")]
+    TokenExchange { status: StatusCode, body: String },
+
+    /// instance id api, used for topic management, responded with error
+    #[error("Instance Id: {status} {body}")]
+    InstanceId { status: StatusCode, body: String },
+
+    /// e.g. credential file doesn't exist or failed to read
+    #[error("Io: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Credential Deserialize: {0}")]
+    CredentialDeserialize(serde_json::Error),
+
+    /// `private_key` of credential doesn't match algorithm, e.g. malformed pem
+    #[error("Invalid Private Key: {0}")]
+    InvalidPrivateKey(jsonwebtoken::errors::Error),
+
+    #[error("Missing Env Var: please set {0}")]
+    MissingEnvVar(String),
+
+    /// [`crate::FcmRegistry`] has no client of the project
+    #[error("Unknown Project: {0}")]
+    UnknownProject(String),
+}
+
+/// comparable category of [`Error`], without sources which can't be compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    ResponseDeserialize,
+    RequestSerialize,
+    MessageDeserialize,
+    InvalidData,
+    InvalidToken,
+    TooManyTokens,
+    ReservedHeader,
+    Reqwest,
+    /// error code of fcm error response
+    SendMessage(FcmErrorCode),
+    TokenExchange,
+    InstanceId,
+    Io,
+    CredentialDeserialize,
+    InvalidPrivateKey,
+    MissingEnvVar,
+    UnknownProject,
+}
+
+impl Error {
+    /// e.g. `assert_eq!(err.kind(), ErrorKind::SendMessage(FcmErrorCode::Unregistered))`
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::ResponseDeserialize { .. } => ErrorKind::ResponseDeserialize,
+            Self::RequestSerialize(_) => ErrorKind::RequestSerialize,
+            Self::MessageDeserialize(_) => ErrorKind::MessageDeserialize,
+            Self::InvalidData(_) => ErrorKind::InvalidData,
+            Self::InvalidToken(_) => ErrorKind::InvalidToken,
+            Self::TooManyTokens(_) => ErrorKind::TooManyTokens,
+            Self::ReservedHeader(_) => ErrorKind::ReservedHeader,
+            Self::Reqwest(_) => ErrorKind::Reqwest,
+            Self::SendMessage { response, .. } => {
+                ErrorKind::SendMessage(response.error.error_code())
+            }
+            Self::TokenExchange { .. } => ErrorKind::TokenExchange,
+            Self::InstanceId { .. } => ErrorKind::InstanceId,
+            Self::Io(_) => ErrorKind::Io,
+            Self::CredentialDeserialize(_) => ErrorKind::CredentialDeserialize,
+            Self::InvalidPrivateKey(_) => ErrorKind::InvalidPrivateKey,
+            Self::MissingEnvVar(_) => ErrorKind::MissingEnvVar,
+            Self::UnknownProject(_) => ErrorKind::UnknownProject,
+        }
+    }
+
+    /// http status of response which caused the error, if any
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::SendMessage { status, .. }
+            | Self::TokenExchange { status, .. }
+            | Self::InstanceId { status, .. } => Some(*status),
+            Self::Reqwest(e) => e.status(),
+            _ => None,
+        }
+    }
+
+    /// request or response body timed out
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Self::Reqwest(e) if e.is_timeout())
+    }
+
+    /// failed to connect, e.g. dns resolution failure or refused connection
+    pub fn is_connect(&self) -> bool {
+        matches!(self, Self::Reqwest(e) if e.is_connect())
+    }
+
+    /// failed to read response body
+    pub fn is_body(&self) -> bool {
+        matches!(self, Self::Reqwest(e) if e.is_body() || e.is_decode())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::StatusCode;
+    use reqwest::Client;
+    use tokio::net::TcpListener;
+
+    use super::{Error, ErrorKind};
+    use crate::FcmErrorCode;
+
+    #[tokio::test]
+    async fn test_is_timeout_and_is_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = Client::builder()
+            .timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+
+        // accepted but never responded
+        let err = Error::from(
+            client
+                .get(format!("http://{addr}"))
+                .send()
+                .await
+                .unwrap_err(),
+        );
+        assert!(err.is_timeout());
+        assert!(!err.is_connect());
+
+        drop(listener);
+
+        let err = Error::from(
+            client
+                .get(format!("http://{addr}"))
+                .send()
+                .await
+                .unwrap_err(),
+        );
+        assert!(err.is_connect());
+        assert!(!err.is_timeout());
+
+        assert!(!Error::InvalidData("x".to_string()).is_timeout());
+    }
+
+    #[test]
+    fn test_kind() {
+        let err = Error::SendMessage {
+            status: StatusCode::NOT_FOUND,
+            response: serde_json::from_str(
+                r#"{"error":{"code":404,"message":"Requested entity was not found.","status":"NOT_FOUND"}}"#,
+            )
+            .unwrap(),
+            retry_after: None,
+        };
+
+        assert_eq!(
+            err.kind(),
+            ErrorKind::SendMessage(FcmErrorCode::Unregistered)
+        );
+        assert_eq!(
+            Error::MissingEnvVar("X".to_string()).kind(),
+            ErrorKind::MissingEnvVar
+        );
+    }
 }
@@ -1,18 +1,70 @@
-use std::{borrow::Cow, fmt::Display, path::Path};
+use std::{
+    borrow::{Borrow, Cow},
+    collections::HashMap,
+    fmt::Display,
+    path::Path,
+    pin::pin,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use http::{header, Method, StatusCode};
+use futures_util::{
+    future::{self, Either},
+    stream, StreamExt,
+};
+use http::{header, HeaderMap, Method, StatusCode};
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use serde::{ser::SerializeStruct, Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
 
 use crate::{
+    android::AndroidConfig,
+    builder::FirebaseCloudMessagingBuilder,
+    data::DataPayload,
     oauth::{Credential, GoogleOAuth2},
+    retry::{self, RetryPolicy},
+    topic::IID_BASE_URL,
+    trace,
+    transport::{FcmTransport, TransportRequest},
+    webpush::WebpushConfig,
     Error,
 };
 
+/// key of `data` which [`Message::correlation_id`] is sent as
+///
+/// reserved, so `data` containing it is rejected with [`Error::InvalidData`]
+pub const CORRELATION_ID_KEY: &str = "__fcm_correlation_id";
+
+/// oauth2 scope required to send messages
+pub const FCM_SCOPE: &str = "https://www.googleapis.com/auth/firebase.messaging";
+
+pub(crate) const BASE_URL: &str = "https://fcm.googleapis.com";
+
+/// cheap to clone, clones share oauth2 token cache and connection pool
+#[derive(Clone)]
 pub struct FirebaseCloudMessaging {
     project_id: String,
-    oauth2: GoogleOAuth2,
-    client: Client,
+    base_url: String,
+    pub(crate) iid_base_url: String,
+    pub(crate) oauth2: Arc<GoogleOAuth2>,
+    pub(crate) client: Client,
+    transport: Arc<dyn FcmTransport>,
+    retry_policy: Option<RetryPolicy>,
+    on_send: Option<SendHook>,
+}
+
+type SendHook = Arc<dyn Fn(&SendEvent) + Send + Sync>;
+
+/// passed to hook of [`FirebaseCloudMessaging::on_send`] once per message, after its retries if any
+#[derive(Debug, Clone)]
+pub struct SendEvent {
+    /// outcome of last attempt
+    pub success: bool,
+    /// total of all attempts, including delays between retries
+    pub latency: Duration,
+    /// `None` if no response was received, e.g. connection failure
+    pub status: Option<StatusCode>,
 }
 
 impl FirebaseCloudMessaging {
@@ -27,14 +79,115 @@ impl FirebaseCloudMessaging {
         Self::from_credential(Credential::from_env())
     }
 
+    /// request timeout is 30 seconds, see [`FirebaseCloudMessaging::builder`] for configuring it
     pub fn from_credential(cred: Credential) -> Self {
+        Self::builder(cred).build()
+    }
+
+    pub fn builder(cred: Credential) -> FirebaseCloudMessagingBuilder {
+        FirebaseCloudMessagingBuilder::new(cred)
+    }
+
+    pub fn from_credential_path_with_client<P>(p: P, client: Client) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_credential_with_client(Credential::from_path(p), client)
+    }
+
+    pub fn from_env_with_client(client: Client) -> Self {
+        Self::from_credential_with_client(Credential::from_env(), client)
+    }
+
+    /// `client` is used for both sending messages and fetching oauth2 token
+    pub fn from_credential_with_client(cred: Credential, client: Client) -> Self {
         Self {
             project_id: cred.project_id.clone(),
-            oauth2: GoogleOAuth2::from_credential(cred, "https://fcm.googleapis.com/".to_string()),
-            client: Client::new(),
+            base_url: BASE_URL.to_string(),
+            iid_base_url: IID_BASE_URL.to_string(),
+            oauth2: GoogleOAuth2::from_credential(cred)
+                .with_client(client.clone())
+                .into(),
+            transport: Arc::new(client.clone()),
+            client,
+            retry_policy: None,
+            on_send: None,
         }
     }
 
+    /// replaces transport of `messages:send` requests, e.g. with [`crate::MockTransport`] for testing
+    ///
+    /// requests of [`FirebaseCloudMessaging::send_to_devices`] don't go through it
+    pub fn with_transport(mut self, transport: Arc<dyn FcmTransport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// project which messages are sent to, defaults to `project_id` of credential
+    pub fn project_id(&self) -> &str {
+        &self.project_id
+    }
+
+    /// sends messages to another project than `project_id` of credential
+    ///
+    /// service account must have permission to send messages in that project
+    pub fn with_project_id(mut self, project_id: impl Into<String>) -> Self {
+        self.project_id = project_id.into();
+        self
+    }
+
+    /// replaces `https://fcm.googleapis.com`, e.g. with address of emulator or mock server
+    ///
+    /// path of requests, e.g. `/v1/projects/{project_id}/messages:send`, is kept
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// replaces `https://iid.googleapis.com`, which topic management and token info are requested to
+    ///
+    /// path of requests, e.g. `/iid/v1:batchAdd`, is kept
+    pub fn with_iid_base_url(mut self, iid_base_url: impl Into<String>) -> Self {
+        self.iid_base_url = iid_base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// currently valid oauth2 token with [`FCM_SCOPE`], which is used by each send
+    ///
+    /// see [`GoogleOAuth2::access_token`]
+    pub async fn access_token(&self) -> crate::Result<String> {
+        self.oauth2.access_token().await
+    }
+
+    /// replaces oauth2 token provider, e.g. with one using custom token endpoint
+    ///
+    /// scope of `oauth2` should be kept as [`FCM_SCOPE`]
+    ///
+    /// token is exchanged with http client of `self`, e.g. configured by [`FirebaseCloudMessaging::builder`],
+    /// unless `oauth2` is given its own by [`GoogleOAuth2::with_client`]
+    pub fn with_oauth2(mut self, oauth2: GoogleOAuth2) -> Self {
+        self.oauth2 = Arc::new(oauth2.with_default_client(self.client.clone()));
+        self
+    }
+
+    /// called once after each message is sent, e.g. to record metrics
+    ///
+    /// retried attempts of [`FirebaseCloudMessaging::with_retry_policy`] are not reported separately
+    ///
+    /// for token exchanges, see [`GoogleOAuth2::on_refresh`]
+    pub fn on_send(mut self, hook: Box<dyn Fn(&SendEvent) + Send + Sync>) -> Self {
+        self.on_send = Some(hook.into());
+        self
+    }
+
+    /// retries on `429`, `500` and `503`, honoring `Retry-After` header up to [`RetryPolicy::max_delay`]
+    ///
+    /// default is no retry
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(retry_policy);
+        self
+    }
+
     /* pub fn new(firebase_token: impl Into<String>, project_id: impl Into<String>) -> Self {
         let client = Client::new();
 
@@ -45,175 +198,797 @@ impl FirebaseCloudMessaging {
         }
     } */
 
-    const BOUNDARY: &'static str = "fcm_rust_sdk";
+    /// `message` can be given by value or by reference, as with other send methods
+    ///
+    /// Reference: https://firebase.google.com/docs/cloud-messaging/send-message#send-messages-to-specific-devices
+    pub async fn send<D>(
+        &self,
+        registration_token: impl Into<String>,
+        message: impl Borrow<Message>,
+        options: SendOptions,
+        data: Option<D>,
+    ) -> crate::Result<SendMessageSuccessResponse>
+    where
+        D: Serialize,
+    {
+        let target = Target::Token(registration_token.into());
 
-    fn add_part<D>(project_id: &str, oauth2_token: &str, xs: &mut Vec<String>, body: Body<'_, D>)
+        self.send_to_target(target, Some(message.borrow()), &options, data.as_ref())
+            .await
+    }
+
+    /// same as [`Self::send`], but dead registration token is not an error
+    pub async fn send_lenient<D>(
+        &self,
+        registration_token: impl Into<String>,
+        message: impl Borrow<Message>,
+        options: SendOptions,
+        data: Option<D>,
+    ) -> crate::Result<SendOutcome>
     where
         D: Serialize,
     {
-        let body = WrappedBody { message: body };
-        let serialized_body = serde_json::to_string_pretty(&body).expect("json serialize");
+        match self.send(registration_token, message, options, data).await {
+            Ok(res) => Ok(SendOutcome::Delivered(res)),
+            Err(Error::SendMessage { response, .. }) if response.error.is_unregistered() => {
+                Ok(SendOutcome::TokenInvalid)
+            }
+            Err(err) => Err(err),
+        }
+    }
 
-        // println!("{}", serialized_body);
+    /// Reference: https://firebase.google.com/docs/cloud-messaging/send-message#send-messages-to-topics
+    pub async fn send_to_topic<D>(
+        &self,
+        topic: impl Into<String>,
+        message: impl Borrow<Message>,
+        options: SendOptions,
+        data: Option<D>,
+    ) -> crate::Result<SendMessageSuccessResponse>
+    where
+        D: Serialize,
+    {
+        let target = Target::Topic(topic.into());
 
-        xs.push(format!("--{}", Self::BOUNDARY));
-        xs.push("Content-Type: application/http".to_string());
-        xs.push("Content-Transfer-Encoding: binary".to_string());
-        xs.push(format!("Authorization: Bearer {}", oauth2_token));
-        xs.push("".to_string());
-        xs.push(format!("POST /v1/projects/{}/messages:send", project_id));
-        xs.push("Content-Type: application/json".to_string());
-        xs.push("accept: application/json".to_string());
-        xs.push("".to_string());
-        xs.push(serialized_body);
+        self.send_to_target(target, Some(message.borrow()), &options, data.as_ref())
+            .await
     }
 
-    fn add_end_boundary(xs: &mut Vec<String>) {
-        xs.push(format!("--{}--\r\n", Self::BOUNDARY));
+    /// sends to every device of legacy device group
+    ///
+    /// Reference: https://firebase.google.com/docs/cloud-messaging/android/device-group
+    pub async fn send_to_notification_key<D>(
+        &self,
+        notification_key: impl Into<String>,
+        message: impl Borrow<Message>,
+        options: SendOptions,
+        data: Option<D>,
+    ) -> crate::Result<SendMessageSuccessResponse>
+    where
+        D: Serialize,
+    {
+        let target = Target::NotificationKey(notification_key.into());
+
+        self.send_to_target(target, Some(message.borrow()), &options, data.as_ref())
+            .await
     }
 
-    /// if registration tokens is empty, returns empty vec and do nothing
+    /// condition is a boolean expression of topics, e.g. `'TopicA' in topics && 'TopicB' in topics`
     ///
-    /// Reference: https://firebase.google.com/docs/cloud-messaging/send-message#send-messages-to-multiple-devices
-    pub async fn send_to_devices<D>(
+    /// Reference: https://firebase.google.com/docs/cloud-messaging/send-message#send-messages-to-topics
+    pub async fn send_to_condition<D>(
         &self,
-        registration_tokens: impl IntoIterator<Item = impl Into<String>>,
-        message: Message,
+        condition: impl Into<String>,
+        message: impl Borrow<Message>,
         options: SendOptions,
         data: Option<D>,
-    ) -> crate::Result<Vec<Result<SendMessageSuccessResponse, SendMessageErrorResponse>>>
+    ) -> crate::Result<SendMessageSuccessResponse>
     where
         D: Serialize,
     {
-        let mut xs = Vec::new();
-        let mut batch_len = 0;
+        let target = Target::Condition(condition.into());
 
-        let oauth2_token = self.oauth2.get_or_update_token();
+        self.send_to_target(target, Some(message.borrow()), &options, data.as_ref())
+            .await
+    }
 
-        for registration_token in registration_tokens {
-            batch_len += 1;
+    /// sends message without notification, e.g. for background delivery
+    ///
+    /// Reference: https://firebase.google.com/docs/cloud-messaging/concept-options#data_messages
+    pub async fn send_data_only<D>(
+        &self,
+        registration_token: impl Into<String>,
+        options: SendOptions,
+        data: D,
+    ) -> crate::Result<SendMessageSuccessResponse>
+    where
+        D: Serialize,
+    {
+        let target = Target::Token(registration_token.into());
 
-            let body = Body {
-                token: registration_token.into(),
-                notification: Cow::Borrowed(&message),
-                apns: options.to_apns_payload().into(),
-                data: data.as_ref(),
-            };
+        self.send_to_target(target, None, &options, Some(&data))
+            .await
+    }
 
-            Self::add_part(&self.project_id, &oauth2_token, &mut xs, body);
-        }
+    /// same as [`Self::send`], but with typed `data` which is always valid for fcm
+    pub async fn send_with_data_payload(
+        &self,
+        registration_token: impl Into<String>,
+        message: impl Borrow<Message>,
+        options: SendOptions,
+        data: DataPayload,
+    ) -> crate::Result<SendMessageSuccessResponse> {
+        self.send(registration_token, message, options, Some(data))
+            .await
+    }
 
-        if batch_len == 0 {
-            return Ok(Vec::new());
-        }
+    /// sends `message` as is, wrapped in `{"message": ...}`, for fields which are not modeled yet
+    ///
+    /// Reference: https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages
+    pub async fn send_raw(
+        &self,
+        message: serde_json::Value,
+    ) -> crate::Result<SendMessageSuccessResponse> {
+        let body = serde_json::json!({ "message": message });
+        let body = serde_json::to_vec(&body).map_err(Error::RequestSerialize)?;
+
+        trace::traced!(
+            self.post_message(body, HeaderMap::new()),
+            "fcm.send",
+            project_id = %self.project_id,
+        )
+    }
 
-        Self::add_end_boundary(&mut xs);
+    /// sends message to each device concurrently, at most [`Self::MULTICAST_CONCURRENCY`] requests in flight
+    ///
+    /// results are in the same order as `registration_tokens`
+    pub async fn send_multicast<D>(
+        &self,
+        registration_tokens: impl IntoIterator<Item = impl Into<String>>,
+        message: impl Borrow<Message>,
+        options: SendOptions,
+        data: Option<D>,
+    ) -> Vec<crate::Result<SendMessageSuccessResponse>>
+    where
+        D: Serialize,
+    {
+        let (message, options, data) = (message.borrow(), &options, data.as_ref());
 
-        let body = xs.join("\r\n");
+        stream::iter(registration_tokens)
+            .map(|registration_token| {
+                let target = Target::Token(registration_token.into());
 
-        // println!("{body}");
+                self.send_to_target(target, Some(message), options, data)
+            })
+            .buffered(Self::MULTICAST_CONCURRENCY)
+            .collect()
+            .await
+    }
 
-        // curl --data-binary @batch_request.txt -H 'Content-Type: multipart/mixed; boundary="subrequest_boundary"' https://fcm.googleapis.com/batch
-        let req = self
-            .client
-            .request(Method::POST, "https://fcm.googleapis.com/batch")
-            .header(
-                header::CONTENT_TYPE,
-                format!("multipart/mixed; boundary={}", Self::BOUNDARY),
-            )
-            .body(body);
+    pub const MULTICAST_CONCURRENCY: usize = 32;
 
-        // println!("{req:#?}");
+    /// same as [`Self::send_multicast`], but collects dead registration tokens to be removed
+    pub async fn send_multicast_pruning<D>(
+        &self,
+        registration_tokens: impl IntoIterator<Item = impl Into<String>>,
+        message: impl Borrow<Message>,
+        options: SendOptions,
+        data: Option<D>,
+    ) -> MulticastOutcome
+    where
+        D: Serialize,
+    {
+        let registration_tokens = registration_tokens
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<String>>();
 
-        let res = req.send().await?;
+        let results = self
+            .send_multicast(registration_tokens.iter().cloned(), message, options, data)
+            .await;
 
-        // println!("{res:#?}");
+        let mut outcome = MulticastOutcome::default();
 
-        let res_content_type = res
-            .headers()
-            .get(header::CONTENT_TYPE)
-            .and_then(|x| x.to_str().ok())
-            .unwrap_or("");
+        for (registration_token, result) in registration_tokens.into_iter().zip(results) {
+            match result {
+                Ok(_) => outcome.success_count += 1,
+                Err(Error::SendMessage { response, .. }) if response.error.is_unregistered() => {
+                    outcome.failure_count += 1;
+                    outcome.unregistered_tokens.push(registration_token);
+                }
+                Err(err) => {
+                    outcome.failure_count += 1;
+                    outcome.errors.push((registration_token, err));
+                }
+            }
+        }
 
-        let res_boundary = Self::parse_boundary(res_content_type);
+        outcome
+    }
 
-        match (res.status(), res_boundary) {
-            (StatusCode::OK, Some(res_boundary)) => {
-                let res = res.text().await?;
+    /// sends each message to its device, at most `concurrency` requests in flight
+    ///
+    /// results are in the same order as `targets`, paired with registration token of each
+    pub async fn send_many<'a, D>(
+        &self,
+        targets: impl IntoIterator<Item = (impl Into<String>, &'a Message)>,
+        options: SendOptions,
+        data: Option<D>,
+        concurrency: usize,
+    ) -> Vec<(String, crate::Result<SendMessageSuccessResponse>)>
+    where
+        D: Serialize,
+    {
+        let cancel = CancellationToken::new();
 
-                // println!("{res}");
+        self.send_many_cancellable(targets, options, data, concurrency, &cancel)
+            .await
+    }
 
-                let res = Self::parse_batch_response(res.trim(), &res_boundary, batch_len)?;
+    /// same as [`Self::send_many`], but stops sending when `cancel` is cancelled, e.g. on shutdown
+    ///
+    /// requests in flight are completed, and messages which were not sent yet are left out of results
+    pub async fn send_many_cancellable<'a, D>(
+        &self,
+        targets: impl IntoIterator<Item = (impl Into<String>, &'a Message)>,
+        options: SendOptions,
+        data: Option<D>,
+        concurrency: usize,
+        cancel: &CancellationToken,
+    ) -> Vec<(String, crate::Result<SendMessageSuccessResponse>)>
+    where
+        D: Serialize,
+    {
+        let semaphore = Semaphore::new(concurrency.max(1));
+        let (semaphore, options, data) = (&semaphore, &options, data.as_ref());
 
-                Ok(res)
-            }
+        let xs = targets.into_iter().map(|(registration_token, message)| {
+            let registration_token = registration_token.into();
 
-            _ => {
-                let res = res.text().await?;
+            async move {
+                let acquire = pin!(semaphore.acquire());
+                let cancelled = pin!(cancel.cancelled());
 
-                let error: SendMessageErrorResponse =
-                    serde_json::from_str(&res).map_err(Error::ResponseDeserialize)?;
+                let _permit = match future::select(acquire, cancelled).await {
+                    Either::Left((permit, _)) if !cancel.is_cancelled() => {
+                        permit.expect("semaphore is never closed")
+                    }
+                    _ => return None,
+                };
 
-                Err(Error::SendMessage(error))
+                let target = Target::Token(registration_token.clone());
+                let r = self
+                    .send_to_target(target, Some(message), options, data)
+                    .await;
+
+                Some((registration_token, r))
             }
-        }
+        });
+
+        future::join_all(xs).await.into_iter().flatten().collect()
     }
 
-    fn parse_batch_response(
-        x: &str,
-        boundary: &str,
-        batch_len: usize,
-    ) -> crate::Result<Vec<Result<SendMessageSuccessResponse, SendMessageErrorResponse>>> {
-        // println!("{x}");
-        // println!("{batch_len}");
-        // println!("boundary = {boundary}");
+    /// same as [`Self::send`], but `headers` are added to `messages:send` request
+    ///
+    /// e.g. `X-Goog-User-Project` to attribute quota and billing to another project
+    ///
+    /// `Authorization` and `Content-Type` are set by the client, so they are rejected with [`Error::ReservedHeader`]
+    pub async fn send_with_headers<D>(
+        &self,
+        registration_token: impl Into<String>,
+        message: impl Borrow<Message>,
+        options: SendOptions,
+        data: Option<D>,
+        headers: HeaderMap,
+    ) -> crate::Result<SendMessageSuccessResponse>
+    where
+        D: Serialize,
+    {
+        if let Some(name) = [header::AUTHORIZATION, header::CONTENT_TYPE]
+            .into_iter()
+            .find(|x| headers.contains_key(x))
+        {
+            return Err(Error::ReservedHeader(name.to_string()));
+        }
 
-        x.split(&format!("--{boundary}"))
-            .map(|x| x.trim())
-            .filter(|x| !x.is_empty())
-            .take(batch_len)
-            .map(Self::parse_response)
-            .collect::<Result<Vec<_>, _>>()
+        let target = Target::Token(registration_token.into());
+
+        self.send_to_target_with_headers(
+            target,
+            Some(message.borrow()),
+            &options,
+            data.as_ref(),
+            headers,
+        )
+        .await
     }
 
-    fn parse_response(
-        x: &str,
-    ) -> crate::Result<Result<SendMessageSuccessResponse, SendMessageErrorResponse>> {
-        let x = x.split("\r\n\r\n").last().unwrap_or_default();
-        let xx = x.split("\r\n").next().unwrap_or_default();
+    async fn send_to_target<D>(
+        &self,
+        target: Target,
+        message: Option<&Message>,
+        options: &SendOptions,
+        data: Option<&D>,
+    ) -> crate::Result<SendMessageSuccessResponse>
+    where
+        D: Serialize,
+    {
+        self.send_to_target_with_headers(target, message, options, data, HeaderMap::new())
+            .await
+    }
 
-        // println!("x = {x:?}");
-        // println!("xx = {xx:?}");
+    async fn send_to_target_with_headers<D>(
+        &self,
+        target: Target,
+        message: Option<&Message>,
+        options: &SendOptions,
+        data: Option<&D>,
+        headers: HeaderMap,
+    ) -> crate::Result<SendMessageSuccessResponse>
+    where
+        D: Serialize,
+    {
+        #[cfg(feature = "tracing")]
+        let redacted_target = trace::redact(&target);
 
-        match serde_json::from_str(xx) {
-            Ok(r) => Ok(Ok(r)),
-            Err(_) => {
-                if let Ok(r) = serde_json::from_str(x) {
-                    Ok(Ok(r))
-                } else {
-                    let r = serde_json::from_str(x).map_err(Error::ResponseDeserialize)?;
-                    Ok(Err(r))
-                }
-            }
-        }
-    }
+        let body = Self::serialize_body(target, message, options, data)?;
 
-    fn parse_boundary(x: &str) -> Option<String> {
-        let r = x
-            .split(';')
-            .map(|x| x.trim())
-            .find(|x| x.starts_with("boundary="))?
-            .replacen("boundary=", "", 1);
+        let mut res = trace::traced!(
+            self.post_message(body, headers),
+            "fcm.send",
+            project_id = %self.project_id,
+            target = %redacted_target,
+        )?;
 
-        Some(r)
+        res.correlation_id = message.and_then(|x| x.correlation_id.clone());
+
+        Ok(res)
     }
-}
 
-#[cfg_attr(test, derive(Eq, PartialEq))]
-#[derive(Debug, Deserialize)]
-pub struct SendMessageSuccessResponse {
-    pub name: String,
-}
+    /// posts serialized body to `messages:send`, with auth, retry and error parsing
+    pub(crate) async fn post_message(
+        &self,
+        body: Vec<u8>,
+        headers: HeaderMap,
+    ) -> crate::Result<SendMessageSuccessResponse> {
+        let start = Instant::now();
+
+        let res = self.try_post_message(body, headers).await;
+
+        if let Some(on_send) = &self.on_send {
+            on_send(&SendEvent {
+                success: res.is_ok(),
+                latency: start.elapsed(),
+                status: match &res {
+                    Ok(_) => Some(StatusCode::OK),
+                    Err(err) => err.status(),
+                },
+            });
+        }
+
+        res
+    }
+
+    async fn try_post_message(
+        &self,
+        body: Vec<u8>,
+        headers: HeaderMap,
+    ) -> crate::Result<SendMessageSuccessResponse> {
+        let mut oauth2_token = match self.transport.requires_auth() {
+            true => Some(self.oauth2.access_token().await?),
+            false => None,
+        };
+
+        let mut attempt = 0;
+        // token may be rejected even if it looks fresh, e.g. rotated service account
+        let mut refreshed = false;
+
+        let res = loop {
+            let req = TransportRequest {
+                url: Self::send_url(&self.base_url, &self.project_id),
+                bearer_token: oauth2_token.clone(),
+                headers: headers.clone(),
+                body: body.clone(),
+            };
+
+            let res = self.transport.post(req).await?;
+
+            if let (StatusCode::UNAUTHORIZED, Some(rejected), false) =
+                (res.status, &oauth2_token, refreshed)
+            {
+                refreshed = true;
+                oauth2_token = Some(self.oauth2.force_refresh(rejected).await?);
+                continue;
+            }
+
+            let delay = self
+                .retry_policy
+                .as_ref()
+                .and_then(|x| x.delay(attempt, res.status, &res.headers));
+
+            match delay {
+                Some(delay) => {
+                    attempt += 1;
+                    tokio::time::sleep(delay).await;
+                }
+                None => break res,
+            }
+        };
+
+        let status = res.status;
+        let retry_after = retry::parse_retry_after(&res.headers);
+
+        trace::record_status(status);
+
+        Self::parse_send_response(status, retry_after, res.body)
+    }
+
+    pub(crate) fn parse_send_response(
+        status: StatusCode,
+        retry_after: Option<Duration>,
+        res: String,
+    ) -> crate::Result<SendMessageSuccessResponse> {
+        if status == StatusCode::OK {
+            serde_json::from_str(&res).map_err(|source| Error::ResponseDeserialize {
+                source,
+                body: res.clone(),
+            })
+        } else {
+            Err(Self::parse_error(&res, status, retry_after))
+        }
+    }
+
+    /// returns json body which would be sent to `messages:send`, without sending it
+    pub fn build_request_body<D>(
+        &self,
+        target: Target,
+        message: Option<&Message>,
+        options: &SendOptions,
+        data: Option<&D>,
+    ) -> crate::Result<serde_json::Value>
+    where
+        D: Serialize,
+    {
+        Self::validate_target(&target)?;
+        Self::validate_data(data)?;
+
+        let body = WrappedBody::new(Body::new(target, message, options, data), options);
+
+        serde_json::to_value(&body).map_err(Error::RequestSerialize)
+    }
+
+    /// same as [`Self::build_request_body`], but serialized to bytes of `messages:send` request
+    pub(crate) fn serialize_body<D>(
+        target: Target,
+        message: Option<&Message>,
+        options: &SendOptions,
+        data: Option<&D>,
+    ) -> crate::Result<Vec<u8>>
+    where
+        D: Serialize,
+    {
+        Self::validate_target(&target)?;
+        Self::validate_data(data)?;
+
+        let body = WrappedBody::new(Body::new(target, message, options, data), options);
+
+        serde_json::to_vec(&body).map_err(Error::RequestSerialize)
+    }
+
+    pub(crate) fn send_url(base_url: &str, project_id: &str) -> String {
+        format!("{base_url}/v1/projects/{project_id}/messages:send")
+    }
+
+    /// longer than any registration token or notification key fcm issues
+    const MAX_TOKEN_LEN: usize = 4096;
+
+    /// rejects registration token or notification key which fcm would reject with `INVALID_ARGUMENT`, without sending it
+    ///
+    /// only emptiness, length and whitespace or non ascii characters are checked, as format of tokens is not documented
+    pub(crate) fn validate_target(target: &Target) -> crate::Result<()> {
+        let (Target::Token(token) | Target::NotificationKey(token)) = target else {
+            return Ok(());
+        };
+
+        if token.is_empty() {
+            return Err(Error::InvalidToken("token is empty".to_string()));
+        }
+        if token.len() > Self::MAX_TOKEN_LEN {
+            return Err(Error::InvalidToken(format!(
+                "token is longer than {} bytes",
+                Self::MAX_TOKEN_LEN
+            )));
+        }
+        if !token.bytes().all(|x| x.is_ascii_graphic()) {
+            return Err(Error::InvalidToken(
+                "token contains whitespace or non ascii character".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// fcm accepts only flat map of string to string as `data`, without [`CORRELATION_ID_KEY`]
+    fn validate_data<D>(data: Option<&D>) -> crate::Result<()>
+    where
+        D: Serialize,
+    {
+        let Some(data) = data else {
+            return Ok(());
+        };
+
+        match serde_json::to_value(data).map_err(Error::RequestSerialize)? {
+            serde_json::Value::Object(xs) if xs.contains_key(CORRELATION_ID_KEY) => Err(
+                Error::InvalidData(format!("`{CORRELATION_ID_KEY}` is reserved")),
+            ),
+            serde_json::Value::Object(xs) => match xs.iter().find(|(_, v)| !v.is_string()) {
+                Some((k, _)) => Err(Error::InvalidData(format!(
+                    "value of `{k}` is not a string"
+                ))),
+                None => Ok(()),
+            },
+            _ => Err(Error::InvalidData("data is not a map".to_string())),
+        }
+    }
+
+    fn parse_error(x: &str, status: StatusCode, retry_after: Option<Duration>) -> Error {
+        match serde_json::from_str(x) {
+            Ok(response) => Error::SendMessage {
+                status,
+                response,
+                retry_after,
+            },
+            Err(source) => Error::ResponseDeserialize {
+                source,
+                body: x.to_string(),
+            },
+        }
+    }
+
+    const BOUNDARY: &'static str = "fcm_rust_sdk";
+
+    fn add_part<D>(
+        project_id: &str,
+        oauth2_token: &str,
+        xs: &mut Vec<String>,
+        body: WrappedBody<'_, D>,
+    ) -> crate::Result<()>
+    where
+        D: Serialize,
+    {
+        let serialized_body =
+            serde_json::to_string_pretty(&body).map_err(Error::RequestSerialize)?;
+
+        // println!("{}", serialized_body);
+
+        xs.push(format!("--{}", Self::BOUNDARY));
+        xs.push("Content-Type: application/http".to_string());
+        xs.push("Content-Transfer-Encoding: binary".to_string());
+        xs.push(format!("Authorization: Bearer {}", oauth2_token));
+        xs.push("".to_string());
+        xs.push(format!("POST /v1/projects/{}/messages:send", project_id));
+        xs.push("Content-Type: application/json".to_string());
+        xs.push("accept: application/json".to_string());
+        xs.push("".to_string());
+        xs.push(serialized_body);
+
+        Ok(())
+    }
+
+    fn add_end_boundary(xs: &mut Vec<String>) {
+        xs.push(format!("--{}--\r\n", Self::BOUNDARY));
+    }
+
+    /// if registration tokens is empty, returns empty vec and do nothing
+    ///
+    /// malformed tokens are not sent, and their results are `INVALID_ARGUMENT` errors, as fcm would respond
+    ///
+    /// Reference: https://firebase.google.com/docs/cloud-messaging/send-message#send-messages-to-multiple-devices
+    pub async fn send_to_devices<D>(
+        &self,
+        registration_tokens: impl IntoIterator<Item = impl Into<String>>,
+        message: impl Borrow<Message>,
+        options: SendOptions,
+        data: Option<D>,
+    ) -> crate::Result<Vec<Result<SendMessageSuccessResponse, SendMessageErrorResponse>>>
+    where
+        D: Serialize,
+    {
+        let mut xs = Vec::new();
+        let mut batch_len = 0;
+        // error of each token which is not sent, `None` for tokens which are sent
+        let mut rejected = Vec::new();
+
+        Self::validate_data(data.as_ref())?;
+
+        let oauth2_token = self.oauth2.access_token().await?;
+
+        for registration_token in registration_tokens {
+            let target = Target::Token(registration_token.into());
+
+            if let Err(Error::InvalidToken(message)) = Self::validate_target(&target) {
+                rejected.push(Some(SendMessageErrorResponse {
+                    error: SendMessageError {
+                        code: StatusCode::BAD_REQUEST.as_u16(),
+                        message,
+                        status: "INVALID_ARGUMENT".to_string(),
+                        details: Vec::new(),
+                    },
+                }));
+                continue;
+            }
+
+            batch_len += 1;
+            rejected.push(None);
+
+            let body = Body::new(target, Some(message.borrow()), &options, data.as_ref());
+            let body = WrappedBody::new(body, &options);
+
+            Self::add_part(&self.project_id, &oauth2_token, &mut xs, body)?;
+        }
+
+        if batch_len == 0 {
+            return Ok(Self::merge_rejected(rejected, Vec::new(), None));
+        }
+
+        Self::add_end_boundary(&mut xs);
+
+        let body = xs.join("\r\n");
+
+        // println!("{body}");
+
+        // curl --data-binary @batch_request.txt -H 'Content-Type: multipart/mixed; boundary="subrequest_boundary"' https://fcm.googleapis.com/batch
+        let req = self
+            .client
+            .request(Method::POST, format!("{}/batch", self.base_url))
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/mixed; boundary={}", Self::BOUNDARY),
+            )
+            .body(body);
+
+        // println!("{req:#?}");
+
+        let res = req.send().await?;
+
+        // println!("{res:#?}");
+
+        let res_content_type = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|x| x.to_str().ok())
+            .unwrap_or("");
+
+        let res_boundary = Self::parse_boundary(res_content_type);
+
+        match (res.status(), res_boundary) {
+            (StatusCode::OK, Some(res_boundary)) => {
+                let res = res.text().await?;
+
+                // println!("{res}");
+
+                let res = Self::parse_batch_response(res.trim(), &res_boundary, batch_len)?;
+
+                Ok(Self::merge_rejected(
+                    rejected,
+                    res,
+                    message.borrow().correlation_id.as_deref(),
+                ))
+            }
+
+            _ => {
+                let status = res.status();
+                let retry_after = retry::parse_retry_after(res.headers());
+                let res = res.text().await?;
+
+                Err(Self::parse_error(&res, status, retry_after))
+            }
+        }
+    }
+
+    /// puts results of sent tokens in place of `None`s of `rejected`, with `correlation_id` of message
+    fn merge_rejected(
+        rejected: Vec<Option<SendMessageErrorResponse>>,
+        sent: Vec<Result<SendMessageSuccessResponse, SendMessageErrorResponse>>,
+        correlation_id: Option<&str>,
+    ) -> Vec<Result<SendMessageSuccessResponse, SendMessageErrorResponse>> {
+        let mut sent = sent.into_iter().map(|x| {
+            x.map(|mut x| {
+                x.correlation_id = correlation_id.map(str::to_string);
+                x
+            })
+        });
+
+        rejected
+            .into_iter()
+            .filter_map(|x| match x {
+                Some(x) => Some(Err(x)),
+                None => sent.next(),
+            })
+            .collect()
+    }
+
+    fn parse_batch_response(
+        x: &str,
+        boundary: &str,
+        batch_len: usize,
+    ) -> crate::Result<Vec<Result<SendMessageSuccessResponse, SendMessageErrorResponse>>> {
+        // println!("{x}");
+        // println!("{batch_len}");
+        // println!("boundary = {boundary}");
+
+        x.split(&format!("--{boundary}"))
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .take(batch_len)
+            .map(Self::parse_response)
+            .collect::<Result<Vec<_>, _>>()
+    }
+
+    fn parse_response(
+        x: &str,
+    ) -> crate::Result<Result<SendMessageSuccessResponse, SendMessageErrorResponse>> {
+        let x = x.split("\r\n\r\n").last().unwrap_or_default();
+        let xx = x.split("\r\n").next().unwrap_or_default();
+
+        // println!("x = {x:?}");
+        // println!("xx = {xx:?}");
+
+        match serde_json::from_str(xx) {
+            Ok(r) => Ok(Ok(r)),
+            Err(_) => {
+                if let Ok(r) = serde_json::from_str(x) {
+                    Ok(Ok(r))
+                } else {
+                    let r =
+                        serde_json::from_str(x).map_err(|source| Error::ResponseDeserialize {
+                            source,
+                            body: x.to_string(),
+                        })?;
+                    Ok(Err(r))
+                }
+            }
+        }
+    }
+
+    fn parse_boundary(x: &str) -> Option<String> {
+        let r = x
+            .split(';')
+            .map(|x| x.trim())
+            .find(|x| x.starts_with("boundary="))?
+            .replacen("boundary=", "", 1);
+
+        Some(r)
+    }
+}
+
+#[cfg_attr(test, derive(Eq, PartialEq))]
+#[derive(Debug, Deserialize)]
+pub struct SendMessageSuccessResponse {
+    /// e.g. `projects/myproj/messages/0:1500415314455276%31bd1c9631bd1c96`
+    pub name: String,
+
+    /// [`Message::correlation_id`] of sent message, not part of response
+    #[serde(skip)]
+    pub correlation_id: Option<String>,
+}
+
+impl SendMessageSuccessResponse {
+    /// e.g. `0:1500415314455276%31bd1c9631bd1c96`
+    pub fn message_id(&self) -> &str {
+        self.name
+            .split_once("/messages/")
+            .map_or(self.name.as_str(), |(_, x)| x)
+    }
+
+    /// e.g. `myproj`
+    pub fn project(&self) -> &str {
+        self.name
+            .strip_prefix("projects/")
+            .and_then(|x| x.split('/').next())
+            .unwrap_or_default()
+    }
+}
 
 #[cfg_attr(test, derive(Eq, PartialEq))]
 #[derive(Debug, Deserialize)]
@@ -221,178 +996,2433 @@ pub struct SendMessageErrorResponse {
     pub error: SendMessageError,
 }
 
-#[cfg_attr(test, derive(Eq, PartialEq))]
-#[derive(Debug, Deserialize)]
-pub struct SendMessageError {
-    pub code: u16,
-    pub message: String,
-    pub status: String,
-}
+#[cfg_attr(test, derive(Eq, PartialEq))]
+#[derive(Debug, Deserialize)]
+pub struct SendMessageError {
+    pub code: u16,
+    pub message: String,
+    pub status: String,
+    #[serde(default)]
+    pub details: Vec<SendMessageErrorDetail>,
+}
+
+impl SendMessageError {
+    /// from `errorCode` of `google.firebase.fcm.v1.FcmError` in `details`, or from `status` if it is absent
+    pub fn error_code(&self) -> FcmErrorCode {
+        self.details
+            .iter()
+            .filter(|x| x.r#type == SendMessageErrorDetail::FCM_ERROR)
+            .find_map(|x| x.error_code.as_deref())
+            .map(FcmErrorCode::from_error_code)
+            .unwrap_or_else(|| FcmErrorCode::from_status(&self.status))
+    }
+
+    /// registration token is dead, and it should be removed
+    pub fn is_unregistered(&self) -> bool {
+        self.error_code() == FcmErrorCode::Unregistered || self.status == "NOT_FOUND"
+    }
+
+    /// whether same request may succeed later, with exponential backoff
+    ///
+    /// `UNAVAILABLE`, `INTERNAL` and `QUOTA_EXCEEDED` are transient, others such as
+    /// `INVALID_ARGUMENT`, `UNREGISTERED` and `SENDER_ID_MISMATCH` fail again however many times retried
+    ///
+    /// Reference: https://firebase.google.com/docs/reference/fcm/rest/v1/ErrorCode
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.error_code(),
+            FcmErrorCode::Unavailable | FcmErrorCode::Internal | FcmErrorCode::QuotaExceeded
+        )
+    }
+
+    /// fields of message rejected with `INVALID_ARGUMENT`
+    pub fn field_violations(&self) -> impl Iterator<Item = &FieldViolation> {
+        self.details.iter().flat_map(|x| &x.field_violations)
+    }
+}
+
+/// result of [`FirebaseCloudMessaging::send_lenient`]
+#[derive(Debug)]
+#[cfg_attr(test, derive(Eq, PartialEq))]
+pub enum SendOutcome {
+    Delivered(SendMessageSuccessResponse),
+    /// registration token returned `UNREGISTERED` or `NOT_FOUND`, and should be removed
+    TokenInvalid,
+}
+
+/// result of [`FirebaseCloudMessaging::send_multicast_pruning`]
+#[derive(Debug, Default)]
+pub struct MulticastOutcome {
+    pub success_count: usize,
+    /// including unregistered tokens
+    pub failure_count: usize,
+    /// tokens which returned `UNREGISTERED` or `NOT_FOUND`, and should be removed
+    pub unregistered_tokens: Vec<String>,
+    /// other failures, which may be retried later
+    pub errors: Vec<(String, Error)>,
+}
+
+#[cfg_attr(test, derive(Eq, PartialEq))]
+#[derive(Debug, Deserialize)]
+pub struct SendMessageErrorDetail {
+    /// e.g. `type.googleapis.com/google.firebase.fcm.v1.FcmError`
+    #[serde(rename = "@type")]
+    pub r#type: String,
+    /// only in `google.firebase.fcm.v1.FcmError`
+    #[serde(rename = "errorCode")]
+    pub error_code: Option<String>,
+    /// only in `google.rpc.BadRequest`, which fields of message were rejected
+    #[serde(rename = "fieldViolations", default)]
+    pub field_violations: Vec<FieldViolation>,
+    /// other fields of detail, as is
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl SendMessageErrorDetail {
+    const FCM_ERROR: &'static str = "type.googleapis.com/google.firebase.fcm.v1.FcmError";
+}
+
+#[cfg_attr(test, derive(Eq, PartialEq))]
+#[derive(Debug, Deserialize)]
+pub struct FieldViolation {
+    /// e.g. `message.android.ttl`
+    pub field: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// Reference: https://firebase.google.com/docs/reference/fcm/rest/v1/ErrorCode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FcmErrorCode {
+    UnspecifiedError,
+    /// request parameters were invalid, e.g. malformed registration token or payload
+    InvalidArgument,
+    /// registration token is no longer valid, it should not be used anymore
+    Unregistered,
+    /// registration token is tied to a different sender
+    SenderIdMismatch,
+    /// sending limit exceeded
+    QuotaExceeded,
+    /// server is overloaded
+    Unavailable,
+    /// unknown internal error of server
+    Internal,
+    /// apns certificate or web push auth key was invalid or missing
+    ThirdPartyAuthError,
+    /// `PERMISSION_DENIED` without fcm error code, e.g. missing iam permission or disabled api
+    ///
+    /// unlike [`Self::SenderIdMismatch`], registration token may still be valid
+    PermissionDenied,
+    /// not one of fcm error codes, e.g. `UNAUTHENTICATED`
+    Unknown,
+}
+
+impl FcmErrorCode {
+    fn from_error_code(x: &str) -> Self {
+        match x {
+            "UNSPECIFIED_ERROR" => Self::UnspecifiedError,
+            "INVALID_ARGUMENT" => Self::InvalidArgument,
+            "UNREGISTERED" => Self::Unregistered,
+            "SENDER_ID_MISMATCH" => Self::SenderIdMismatch,
+            "QUOTA_EXCEEDED" => Self::QuotaExceeded,
+            "UNAVAILABLE" => Self::Unavailable,
+            "INTERNAL" => Self::Internal,
+            "THIRD_PARTY_AUTH_ERROR" => Self::ThirdPartyAuthError,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// canonical status which each error code comes with
+    fn from_status(x: &str) -> Self {
+        match x {
+            "NOT_FOUND" => Self::Unregistered,
+            "PERMISSION_DENIED" => Self::PermissionDenied,
+            "RESOURCE_EXHAUSTED" => Self::QuotaExceeded,
+            x => Self::from_error_code(x),
+        }
+    }
+}
+
+impl Display for SendMessageErrorResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error.message)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct WrappedBody<'a, D>
+where
+    D: Serialize,
+{
+    /// validates message without delivering it
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    validate_only: bool,
+
+    message: Body<'a, D>,
+}
+
+impl<'a, D> WrappedBody<'a, D>
+where
+    D: Serialize,
+{
+    fn new(message: Body<'a, D>, options: &SendOptions) -> Self {
+        Self {
+            validate_only: options.validate_only.unwrap_or(false),
+            message,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// can be persisted with serde, e.g. for durable notification queue
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SendOptions {
+    pub content_available: Option<bool>,
+
+    pub mutable_content: Option<bool>,
+
+    pub priority: Option<Priority>,
+
+    /// app icon badge number, `0` removes badge
+    pub badge: Option<u32>,
+
+    pub sound: Option<ApnsSound>,
+
+    /// displayed below title of notification on ios
+    pub subtitle: Option<String>,
+
+    /// overrides `Message`'s title on ios
+    pub apns_title: Option<String>,
+
+    /// overrides `Message`'s body on ios
+    pub apns_body: Option<String>,
+
+    /// app specific keys at root of apns payload alongside `aps`, e.g. deep link identifier
+    pub apns_custom_data: Option<serde_json::Map<String, serde_json::Value>>,
+
+    /// keys of `aps` dictionary which have no typed option, e.g. newly introduced by apple
+    ///
+    /// if it is an object, its keys are merged into `aps` and take precedence over typed options,
+    /// otherwise it is used verbatim as `aps`
+    pub raw_aps: Option<serde_json::Value>,
+
+    /// notifications with same thread id are grouped together
+    pub thread_id: Option<String>,
+
+    /// identifier of notification category registered by app, for actionable notification
+    pub category: Option<String>,
+
+    /// whether notification breaks through focus mode on ios 15+
+    pub interruption_level: Option<ApnsInterruptionLevel>,
+
+    /// `0.0` ~ `1.0`, notification with highest score is featured in notification summary on ios 15+
+    pub relevance_score: Option<f32>,
+
+    /// e.g. `apns-push-type`, `apns-collapse-id`, `apns-expiration`
+    ///
+    /// headers here take precedence over typed options, e.g. `apns-priority` over `priority`
+    ///
+    /// `apns-push-type` is set to `alert` if notification is visible, or `background` if only `content_available` is set
+    ///
+    /// Reference: https://developer.apple.com/documentation/usernotifications/sending-notification-requests-to-apns
+    pub apns_headers: HashMap<String, String>,
+
+    /// `apns-expiration`, apns stops retrying delivery after this time
+    ///
+    /// `UNIX_EPOCH` or earlier means apns tries to deliver only once
+    pub apns_expiration: Option<SystemTime>,
+
+    /// `apns-collapse-id`, notifications with same id replace each other, up to 64 bytes
+    pub apns_collapse_id: Option<String>,
+
+    /// `apns-topic`, bundle id of app which receives notification, e.g. of app clip or extension
+    ///
+    /// fcm fills it with bundle id of registered app if not set
+    pub apns_topic: Option<String>,
+
+    pub android: Option<AndroidConfig>,
+
+    pub webpush: Option<WebpushConfig>,
+
+    pub fcm_options: Option<FcmOptions>,
+
+    /// if true, message is only validated without being delivered (dry run)
+    pub validate_only: Option<bool>,
+}
+
+impl SendOptions {
+    fn to_apns_payload(&self, message: Option<&Message>) -> WrappedApnsPayload {
+        let mutable_content = self.mutable_content.unwrap_or(false);
+        let content_available = self.content_available.unwrap_or(false);
+        let alert = ApsAlert::new(self, message);
+
+        // apns rejects or drops notifications whose push type doesn't match payload
+        let push_type = match (message.is_some() || alert.is_some(), content_available) {
+            (true, _) => Some("alert"),
+            (false, true) => Some("background"),
+            (false, false) => None,
+        };
+
+        // background notifications must have priority 5
+        let default_priority = match push_type {
+            Some("background") => Priority::Normal,
+            _ => Priority::High,
+        };
+        let priority = match self.priority.unwrap_or(default_priority) {
+            Priority::Low => 1,
+            Priority::Normal => 5,
+            Priority::High => 10,
+        };
+
+        let mut headers = self.apns_headers.clone();
+        headers
+            .entry("apns-priority".to_string())
+            .or_insert_with(|| priority.to_string());
+
+        if let Some(push_type) = push_type {
+            headers
+                .entry("apns-push-type".to_string())
+                .or_insert_with(|| push_type.to_string());
+        }
+
+        if let Some(expiration) = self.apns_expiration {
+            let expiration = expiration
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+
+            headers
+                .entry("apns-expiration".to_string())
+                .or_insert_with(|| expiration.to_string());
+        }
+
+        let collapse_id = self
+            .apns_collapse_id
+            .as_ref()
+            .or_else(|| message.and_then(|x| x.collapse_id.as_ref()));
+
+        if let Some(collapse_id) = collapse_id {
+            headers
+                .entry("apns-collapse-id".to_string())
+                .or_insert_with(|| collapse_id.clone());
+        }
+
+        if let Some(topic) = &self.apns_topic {
+            headers
+                .entry("apns-topic".to_string())
+                .or_insert_with(|| topic.clone());
+        }
+
+        WrappedApnsPayload {
+            headers,
+            payload: Aps {
+                aps: ApsDictionary {
+                    typed: ApnsPayload {
+                        alert,
+                        badge: self.badge,
+                        sound: self.sound.clone(),
+                        thread_id: self.thread_id.clone(),
+                        category: self.category.clone(),
+                        interruption_level: self.interruption_level,
+                        relevance_score: self.relevance_score,
+                        mutable_content: if mutable_content { 1 } else { 0 }.into(),
+                        content_available: if content_available { 1 } else { 0 }.into(),
+                    },
+                    raw: self.raw_aps.clone(),
+                },
+                custom_data: self.apns_custom_data.clone(),
+            },
+        }
+    }
+}
+
+/// Reference: https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages#fcmoptions
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FcmOptions {
+    /// label associated with the message's analytics data, e.g. for BigQuery export
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub analytics_label: Option<String>,
+}
+
+/// Reference: https://developer.apple.com/documentation/usernotifications/unnotificationinterruptionlevel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApnsInterruptionLevel {
+    /// added to notification list without lighting up screen or playing sound
+    Passive,
+    /// default behavior, presented immediately unless focus mode is on
+    Active,
+    /// presented immediately even in focus mode, requires time sensitive notifications capability
+    TimeSensitive,
+    /// presented immediately ignoring focus mode and ringer switch, requires entitlement from apple
+    Critical,
+}
+
+/// Reference: https://developer.apple.com/documentation/usernotifications/generating-a-remote-notification#Add-a-sound
+#[derive(Debug, Clone)]
+pub enum ApnsSound {
+    /// system sound
+    Default,
+    /// name of sound file in app bundle or `Library/Sounds`
+    Named(String),
+    /// bypasses do not disturb and ringer switch, requires entitlement from apple
+    Critical {
+        /// `default` or name of sound file
+        name: String,
+        /// 0.0 ~ 1.0
+        volume: f32,
+    },
+}
+
+impl Serialize for ApnsSound {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Self::Default => serializer.serialize_str("default"),
+            Self::Named(name) => serializer.serialize_str(name),
+            Self::Critical { name, volume } => {
+                let mut x = serializer.serialize_struct("ApnsSound", 3)?;
+                x.serialize_field("critical", &1)?;
+                x.serialize_field("name", name)?;
+                x.serialize_field("volume", volume)?;
+                x.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ApnsSound {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Name(String),
+            Critical { name: String, volume: f32 },
+        }
+
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Name(name) if name == "default" => Self::Default,
+            Repr::Name(name) => Self::Named(name),
+            Repr::Critical { name, volume } => Self::Critical { name, volume },
+        })
+    }
+}
+
+/// alert is only built when it is overridden or localized, because fcm builds it from notification otherwise
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ApsAlert {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subtitle: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title_loc_key: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    title_loc_args: Vec<String>,
+    #[serde(rename = "loc-key", skip_serializing_if = "Option::is_none")]
+    body_loc_key: Option<String>,
+    #[serde(rename = "loc-args", skip_serializing_if = "Vec::is_empty")]
+    body_loc_args: Vec<String>,
+}
+
+impl ApsAlert {
+    fn new(options: &SendOptions, message: Option<&Message>) -> Option<Self> {
+        let overridden = options.subtitle.is_some()
+            || options.apns_title.is_some()
+            || options.apns_body.is_some();
+
+        if !overridden && !message.is_some_and(Message::is_localized) {
+            return None;
+        }
+
+        Some(Self {
+            title: options
+                .apns_title
+                .clone()
+                .or_else(|| message.map(|x| x.title.clone())),
+            subtitle: options.subtitle.clone(),
+            body: options
+                .apns_body
+                .clone()
+                .or_else(|| message.map(|x| x.body.clone())),
+            title_loc_key: message.and_then(|x| x.title_loc_key.clone()),
+            title_loc_args: message
+                .map(|x| x.title_loc_args.clone())
+                .unwrap_or_default(),
+            body_loc_key: message.and_then(|x| x.body_loc_key.clone()),
+            body_loc_args: message.map(|x| x.body_loc_args.clone()).unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ApnsPayload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alert: Option<ApsAlert>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    badge: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sound: Option<ApnsSound>,
+    #[serde(rename = "thread-id", skip_serializing_if = "Option::is_none")]
+    thread_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<String>,
+    #[serde(rename = "interruption-level", skip_serializing_if = "Option::is_none")]
+    interruption_level: Option<ApnsInterruptionLevel>,
+    #[serde(rename = "relevance-score", skip_serializing_if = "Option::is_none")]
+    relevance_score: Option<f32>,
+    #[serde(rename = "mutable-content", skip_serializing_if = "Option::is_none")]
+    mutable_content: Option<u8>,
+    #[serde(rename = "content-available", skip_serializing_if = "Option::is_none")]
+    content_available: Option<u8>,
+}
+
+/// typed `aps` with [`SendOptions::raw_aps`] applied
+#[derive(Debug)]
+struct ApsDictionary {
+    typed: ApnsPayload,
+    raw: Option<serde_json::Value>,
+}
+
+impl Serialize for ApsDictionary {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match &self.raw {
+            None => self.typed.serialize(serializer),
+            Some(serde_json::Value::Object(raw)) => {
+                let mut aps = match serde_json::to_value(&self.typed) {
+                    Ok(serde_json::Value::Object(aps)) => aps,
+                    Ok(_) => serde_json::Map::new(),
+                    Err(err) => return Err(serde::ser::Error::custom(err)),
+                };
+                aps.extend(raw.clone());
+                aps.serialize(serializer)
+            }
+            Some(raw) => raw.serialize(serializer),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Aps {
+    aps: ApsDictionary,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    custom_data: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Serialize)]
+struct WrappedApnsPayload {
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    headers: HashMap<String, String>,
+    payload: Aps,
+}
+
+/// target of message, these are mutually exclusive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    /// registration token
+    Token(String),
+    /// topic name, without `/topics/` prefix
+    Topic(String),
+    /// e.g. `'TopicA' in topics && 'TopicB' in topics`
+    Condition(String),
+    /// notification key of legacy device group, sent as `token`
+    ///
+    /// device groups can only be managed with legacy server key, and have at most 20 members
+    #[serde(rename = "token", skip_deserializing)]
+    NotificationKey(String),
+}
+
+#[derive(Debug, Serialize)]
+struct Body<'a, D>
+where
+    D: Serialize,
+{
+    #[serde(flatten)]
+    target: Target,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notification: Option<Notification<'a>>,
+
+    apns: Option<WrappedApnsPayload>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    android: Option<Cow<'a, AndroidConfig>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webpush: Option<&'a WebpushConfig>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fcm_options: Option<&'a FcmOptions>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Data<'a, D>>,
+}
+
+/// `data` of message with correlation id added
+#[derive(Debug, Serialize)]
+struct Data<'a, D>
+where
+    D: Serialize,
+{
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    data: Option<&'a D>,
+
+    #[serde(
+        rename = "__fcm_correlation_id",
+        skip_serializing_if = "Option::is_none"
+    )]
+    correlation_id: Option<&'a str>,
+}
+
+impl<'a, D> Body<'a, D>
+where
+    D: Serialize,
+{
+    fn new(
+        target: Target,
+        message: Option<&'a Message>,
+        options: &'a SendOptions,
+        data: Option<&'a D>,
+    ) -> Self {
+        Self {
+            target,
+            notification: message.map(Notification::from),
+            apns: options.to_apns_payload(message).into(),
+            android: Self::android_config(message, options),
+            webpush: options.webpush.as_ref(),
+            fcm_options: options.fcm_options.as_ref(),
+            data: Self::data(message, data),
+        }
+    }
+
+    fn data(message: Option<&'a Message>, data: Option<&'a D>) -> Option<Data<'a, D>> {
+        let correlation_id = message.and_then(|x| x.correlation_id.as_deref());
+
+        match (data, correlation_id) {
+            (None, None) => None,
+            (data, correlation_id) => Some(Data {
+                data,
+                correlation_id,
+            }),
+        }
+    }
+
+    /// fills collapse key and localization of android notification from `message`, unless set explicitly
+    fn android_config(
+        message: Option<&Message>,
+        options: &'a SendOptions,
+    ) -> Option<Cow<'a, AndroidConfig>> {
+        let Some(message) = message.filter(|x| x.is_localized() || x.collapse_id.is_some()) else {
+            return options.android.as_ref().map(Cow::Borrowed);
+        };
+
+        let mut android = options.android.clone().unwrap_or_default();
+
+        if android.collapse_key.is_none() {
+            android.collapse_key = message.collapse_id.clone();
+        }
+
+        if message.is_localized() {
+            let notification = android.notification.get_or_insert_with(Default::default);
+
+            if notification.title_loc_key.is_none() {
+                notification.title_loc_key = message.title_loc_key.clone();
+                notification.title_loc_args = message.title_loc_args.clone();
+            }
+            if notification.body_loc_key.is_none() {
+                notification.body_loc_key = message.body_loc_key.clone();
+                notification.body_loc_args = message.body_loc_args.clone();
+            }
+        }
+
+        Some(Cow::Owned(android))
+    }
+}
+
+impl<'a, D> Default for Body<'a, D>
+where
+    D: Serialize,
+{
+    fn default() -> Self {
+        Self {
+            target: Target::Token("".to_string()),
+            notification: None,
+            apns: None,
+            android: None,
+            webpush: None,
+            fcm_options: None,
+            data: None,
+        }
+    }
+}
+
+/// top level notification of message, localization of `Message` only goes to platform specific payloads
+#[derive(Debug, Serialize)]
+struct Notification<'a> {
+    title: &'a str,
+    body: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<&'a str>,
+}
+
+impl<'a> From<&'a Message> for Notification<'a> {
+    fn from(message: &'a Message) -> Self {
+        Self {
+            title: &message.title,
+            body: &message.body,
+            image: message.image.as_deref(),
+        }
+    }
+}
+
+/// can be persisted with serde, e.g. for durable notification queue
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Message {
+    pub title: String,
+    pub body: String,
+    /// publicly accessible url of image to be displayed in notification
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+
+    /// key of title string in app resources, used instead of `title` on android and apns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title_loc_key: Option<String>,
+    /// format arguments of `title_loc_key`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub title_loc_args: Vec<String>,
+    /// key of body string in app resources, used instead of `body` on android and apns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_loc_key: Option<String>,
+    /// format arguments of `body_loc_key`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub body_loc_args: Vec<String>,
+
+    /// stable key of notification, sent as both android `collapse_key` and `apns-collapse-id` unless set explicitly
+    ///
+    /// messages with same key replace each other, so that retried message is not delivered twice, up to 64 bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collapse_id: Option<String>,
+
+    /// id of caller's choice sent in `data` as [`CORRELATION_ID_KEY`], e.g. for tracing notification until it is received
+    ///
+    /// returned in [`SendMessageSuccessResponse::correlation_id`], also of each sent token of [`FirebaseCloudMessaging::send_to_devices`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+}
+
+impl Message {
+    pub fn new(title: impl Into<String>, body: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            body: body.into(),
+            ..Default::default()
+        }
+    }
+
+    fn is_localized(&self) -> bool {
+        self.title_loc_key.is_some() || self.body_loc_key.is_some()
+    }
+
+    pub fn builder() -> MessageBuilder {
+        MessageBuilder::default()
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MessageBuilder {
+    message: Message,
+}
+
+impl MessageBuilder {
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.message.title = title.into();
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<String>) -> Self {
+        self.message.body = body.into();
+        self
+    }
+
+    pub fn image(mut self, image: impl Into<String>) -> Self {
+        self.message.image = Some(image.into());
+        self
+    }
+
+    pub fn title_loc(
+        mut self,
+        key: impl Into<String>,
+        args: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.message.title_loc_key = Some(key.into());
+        self.message.title_loc_args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn body_loc(
+        mut self,
+        key: impl Into<String>,
+        args: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.message.body_loc_key = Some(key.into());
+        self.message.body_loc_args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// see [`Message::collapse_id`]
+    pub fn collapse(mut self, id: impl Into<String>) -> Self {
+        self.message.collapse_id = Some(id.into());
+        self
+    }
+
+    /// see [`Message::correlation_id`]
+    pub fn correlation_id(mut self, id: impl Into<String>) -> Self {
+        self.message.correlation_id = Some(id.into());
+        self
+    }
+
+    pub fn build(self) -> Message {
+        self.message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use http::{header, HeaderMap, StatusCode};
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        mock, AndroidConfig, AndroidMessagePriority, AndroidNotification, AndroidSound, Color,
+        Error, ErrorKind, LightSettings, MockTransport, NotificationPriority, RetryPolicy,
+        SendOptions, WebpushConfig, WebpushFcmOptions, WebpushNotification,
+        WebpushNotificationAction,
+    };
+
+    use super::{
+        ApnsInterruptionLevel, ApnsSound, Body, Data, FcmErrorCode, FcmOptions,
+        FirebaseCloudMessaging, Message, Priority, SendEvent, SendMessageError,
+        SendMessageErrorResponse, SendMessageSuccessResponse, SendOutcome, Target, WrappedBody,
+    };
+
+    #[tokio::test]
+    async fn test_send_to_devices_with_invalid_tokens() {
+        let endpoint = mock::serve(vec![mock::token_response("token")]).await;
+
+        // nothing is sent, so unreachable base url is never requested
+        let fcm = fcm()
+            .with_base_url("http://127.0.0.1:1")
+            .with_oauth2(mock::oauth2(&endpoint));
+
+        let actual = fcm
+            .send_to_devices(
+                ["", "a b"],
+                Message::new("title", "body"),
+                SendOptions::default(),
+                None::<()>,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(actual.len(), 2);
+        for x in actual {
+            let x = x.unwrap_err();
+            assert_eq!(x.error.status, "INVALID_ARGUMENT");
+            assert_eq!(x.error.error_code(), FcmErrorCode::InvalidArgument);
+        }
+
+        let ok = |x: &str| {
+            Ok(serde_json::from_str(&format!(r#"{{"name":"projects/p/messages/{x}"}}"#)).unwrap())
+        };
+        let invalid = || {
+            serde_json::from_str::<SendMessageErrorResponse>(
+                r#"{"error":{"code":400,"message":"token is empty","status":"INVALID_ARGUMENT"}}"#,
+            )
+            .unwrap()
+        };
+
+        let actual = FirebaseCloudMessaging::merge_rejected(
+            vec![None, Some(invalid()), None],
+            vec![ok("1"), ok("2")],
+            None,
+        );
+
+        assert_eq!(actual, [ok("1"), Err(invalid()), ok("2")]);
+
+        let actual = FirebaseCloudMessaging::merge_rejected(
+            vec![None, Some(invalid())],
+            vec![ok("1")],
+            Some("order-1"),
+        );
+
+        assert_eq!(
+            actual[0].as_ref().unwrap().correlation_id.as_deref(),
+            Some("order-1")
+        );
+        assert!(actual[1].is_err());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_send_to_devices() {
+        let fcm = FirebaseCloudMessaging::from_credential_path("./firebase.credential.json");
+
+        let registration_token = "";
+        let message = Message::new("title", "body");
+
+        #[derive(Debug, serde::Serialize)]
+        struct Data {
+            thumbnail: &'static str,
+            book_id: &'static str,
+        }
+
+        let a = fcm
+            .send_to_devices(
+                [registration_token],
+                message,
+                SendOptions {
+                    mutable_content: true.into(),
+                    content_available: true.into(),
+                    priority: Priority::High.into(),
+                    ..Default::default()
+                },
+                Data {
+                    thumbnail: "https://file.madome.app/image/library/2699651/thumbnail",
+                    book_id: "2699651",
+                }
+                .into(),
+            )
+            .await;
+
+        println!("{a:?}")
+    }
+
+    #[test]
+    fn test_serialize_target() {
+        let message = Message::new("title", "body");
+
+        let body = Body::<()> {
+            target: Target::Topic("news".to_string()),
+            notification: Some((&message).into()),
+            ..Default::default()
+        };
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(actual["topic"], "news");
+        assert!(actual.get("token").is_none());
+
+        let body = Body::<()> {
+            target: Target::Condition("'a' in topics && 'b' in topics".to_string()),
+            notification: Some((&message).into()),
+            ..Default::default()
+        };
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(actual["condition"], "'a' in topics && 'b' in topics");
+        assert!(actual.get("token").is_none());
+        assert!(actual.get("topic").is_none());
+
+        let body = Body::<()> {
+            target: Target::NotificationKey("notification_key".to_string()),
+            notification: Some((&message).into()),
+            ..Default::default()
+        };
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(actual["token"], "notification_key");
+        assert!(actual.get("notificationkey").is_none());
+    }
+
+    #[test]
+    fn test_serialize_data_only() {
+        let body = Body {
+            target: Target::Token("token".to_string()),
+            data: Some(Data {
+                data: Some(&[("key", "value")].into_iter().collect::<HashMap<_, _>>()),
+                correlation_id: None,
+            }),
+            ..Default::default()
+        };
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert!(actual.get("notification").is_none());
+        assert_eq!(actual["data"]["key"], "value");
+    }
+
+    #[test]
+    fn test_serialize_android() {
+        let options = SendOptions {
+            android: AndroidConfig {
+                collapse_key: "collapse_key".to_string().into(),
+                priority: AndroidMessagePriority::High.into(),
+                ttl: Duration::from_secs(3600).into(),
+                notification: AndroidNotification {
+                    channel_id: "channel_id".to_string().into(),
+                    color: "#ff0000".to_string().into(),
+                    sound: AndroidSound::Default.into(),
+                    default_vibrate_timings: true.into(),
+                    light_settings: LightSettings {
+                        color: Color {
+                            red: 1.0,
+                            green: 0.0,
+                            blue: 0.5,
+                            alpha: None,
+                        },
+                        light_on_duration: Duration::from_millis(500),
+                        light_off_duration: Duration::from_secs(2),
+                    }
+                    .into(),
+                    notification_priority: NotificationPriority::PriorityHigh.into(),
+                    ..Default::default()
+                }
+                .into(),
+                ..Default::default()
+            }
+            .into(),
+            ..Default::default()
+        };
+
+        let body = Body::<()>::new(Target::Token("token".to_string()), None, &options, None);
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(
+            actual["android"],
+            serde_json::json!({
+                "collapse_key": "collapse_key",
+                "priority": "HIGH",
+                "ttl": "3600s",
+                "notification": {
+                    "channel_id": "channel_id",
+                    "color": "#ff0000",
+                    "sound": "default",
+                    "default_vibrate_timings": true,
+                    "light_settings": {
+                        "color": { "red": 1.0, "green": 0.0, "blue": 0.5 },
+                        "light_on_duration": "0.500000000s",
+                        "light_off_duration": "2s",
+                    },
+                    "notification_priority": "PRIORITY_HIGH",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_serialize_webpush() {
+        let options = SendOptions {
+            webpush: WebpushConfig {
+                headers: [("Urgency".to_string(), "high".to_string())].into(),
+                notification: WebpushNotification {
+                    title: "title".to_string().into(),
+                    require_interaction: true.into(),
+                    actions: vec![WebpushNotificationAction {
+                        action: "open".to_string(),
+                        title: "Open".to_string(),
+                        icon: None,
+                    }],
+                    ..Default::default()
+                }
+                .into(),
+                fcm_options: WebpushFcmOptions {
+                    link: "https://example.com/orders/1".to_string().into(),
+                }
+                .into(),
+                ..Default::default()
+            }
+            .into(),
+            ..Default::default()
+        };
+
+        let body = Body::<()>::new(Target::Token("token".to_string()), None, &options, None);
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(
+            actual["webpush"],
+            serde_json::json!({
+                "headers": {
+                    "Urgency": "high",
+                },
+                "notification": {
+                    "title": "title",
+                    "requireInteraction": true,
+                    "actions": [{
+                        "action": "open",
+                        "title": "Open",
+                    }],
+                },
+                "fcm_options": {
+                    "link": "https://example.com/orders/1",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_serialize_fcm_options() {
+        let options = SendOptions {
+            fcm_options: FcmOptions {
+                analytics_label: "campaign".to_string().into(),
+            }
+            .into(),
+            ..Default::default()
+        };
+
+        let body = Body::<()>::new(Target::Token("token".to_string()), None, &options, None);
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(actual["fcm_options"]["analytics_label"], "campaign");
+    }
+
+    #[test]
+    fn test_serialize_validate_only() {
+        let options = SendOptions::default();
+
+        let body = Body::<()>::new(Target::Token("token".to_string()), None, &options, None);
+        let actual = serde_json::to_value(WrappedBody::new(body, &options)).unwrap();
+
+        assert!(actual.get("validate_only").is_none());
+
+        let options = SendOptions {
+            validate_only: true.into(),
+            ..Default::default()
+        };
+
+        let body = Body::<()>::new(Target::Token("token".to_string()), None, &options, None);
+        let actual = serde_json::to_value(WrappedBody::new(body, &options)).unwrap();
+
+        assert_eq!(actual["validate_only"], true);
+        assert_eq!(actual["message"]["token"], "token");
+    }
+
+    #[test]
+    fn test_error_code() {
+        let error: SendMessageErrorResponse = serde_json::from_str(
+            r#"{
+                "error": {
+                    "code": 404,
+                    "message": "Requested entity was not found.",
+                    "status": "NOT_FOUND",
+                    "details": [
+                        {
+                            "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+                            "errorCode": "UNREGISTERED"
+                        }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(error.error.error_code(), FcmErrorCode::Unregistered);
+        assert!(error.error.is_unregistered());
+        assert!(!error.error.is_retryable());
+
+        let error: SendMessageErrorResponse = serde_json::from_str(
+            r#"{
+                "error": {
+                    "code": 429,
+                    "message": "Quota exceeded.",
+                    "status": "RESOURCE_EXHAUSTED"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(error.error.error_code(), FcmErrorCode::QuotaExceeded);
+        assert!(!error.error.is_unregistered());
+        assert!(error.error.is_retryable());
+
+        let error: SendMessageErrorResponse = serde_json::from_str(
+            r#"{
+                "error": {
+                    "code": 400,
+                    "message": "Invalid value at 'message.android.ttl'",
+                    "status": "INVALID_ARGUMENT",
+                    "details": [
+                        {
+                            "@type": "type.googleapis.com/google.rpc.BadRequest",
+                            "fieldViolations": [
+                                {
+                                    "field": "message.android.ttl",
+                                    "description": "Invalid value at 'message.android.ttl'"
+                                }
+                            ]
+                        },
+                        {
+                            "@type": "type.googleapis.com/google.rpc.DebugInfo",
+                            "detail": "stack"
+                        }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(error.error.error_code(), FcmErrorCode::InvalidArgument);
+        assert_eq!(
+            error
+                .error
+                .field_violations()
+                .map(|x| x.field.as_str())
+                .collect::<Vec<_>>(),
+            ["message.android.ttl"]
+        );
+        assert_eq!(error.error.details[1].extra["detail"], "stack");
+
+        let error: SendMessageErrorResponse = serde_json::from_str(
+            r#"{
+                "error": {
+                    "code": 401,
+                    "message": "Request had invalid authentication credentials.",
+                    "status": "UNAUTHENTICATED"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(error.error.error_code(), FcmErrorCode::Unknown);
+
+        let error: SendMessageErrorResponse = serde_json::from_str(
+            r#"{
+                "error": {
+                    "code": 403,
+                    "message": "Permission 'cloudmessaging.messages.create' denied on resource",
+                    "status": "PERMISSION_DENIED"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(error.error.error_code(), FcmErrorCode::PermissionDenied);
+
+        let error: SendMessageErrorResponse = serde_json::from_str(
+            r#"{
+                "error": {
+                    "code": 403,
+                    "message": "SenderId mismatch",
+                    "status": "PERMISSION_DENIED",
+                    "details": [
+                        {
+                            "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+                            "errorCode": "SENDER_ID_MISMATCH"
+                        }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(error.error.error_code(), FcmErrorCode::SenderIdMismatch);
+    }
+
+    #[test]
+    fn test_serialize_apns_headers() {
+        let options = SendOptions {
+            priority: Priority::Normal.into(),
+            apns_headers: [("apns-push-type".to_string(), "background".to_string())].into(),
+            ..Default::default()
+        };
+
+        let body = Body::<()>::new(Target::Token("token".to_string()), None, &options, None);
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(
+            actual["apns"]["headers"],
+            serde_json::json!({
+                "apns-priority": "5",
+                "apns-push-type": "background",
+            })
+        );
+        assert!(actual["apns"]["payload"]["aps"]
+            .get("apns-priority")
+            .is_none());
+
+        let options = SendOptions {
+            priority: Priority::Normal.into(),
+            apns_headers: [("apns-priority".to_string(), "10".to_string())].into(),
+            ..Default::default()
+        };
+
+        let body = Body::<()>::new(Target::Token("token".to_string()), None, &options, None);
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(actual["apns"]["headers"]["apns-priority"], "10");
+
+        let options = SendOptions {
+            apns_expiration: (UNIX_EPOCH + Duration::from_secs(1_700_000_000)).into(),
+            apns_collapse_id: "score".to_string().into(),
+            apns_topic: "com.example.app.clip".to_string().into(),
+            ..Default::default()
+        };
+
+        let body = Body::<()>::new(Target::Token("token".to_string()), None, &options, None);
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(
+            actual["apns"]["headers"],
+            serde_json::json!({
+                "apns-priority": "10",
+                "apns-expiration": "1700000000",
+                "apns-collapse-id": "score",
+                "apns-topic": "com.example.app.clip",
+            })
+        );
+    }
+
+    #[test]
+    fn test_serialize_aps() {
+        let message = Message::new("title", "body");
+        let options = SendOptions {
+            badge: 3.into(),
+            sound: ApnsSound::Default.into(),
+            subtitle: "subtitle".to_string().into(),
+            thread_id: "thread_id".to_string().into(),
+            category: "category".to_string().into(),
+            ..Default::default()
+        };
+
+        let body = Body::<()>::new(
+            Target::Token("token".to_string()),
+            Some(&message),
+            &options,
+            None,
+        );
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(
+            actual["apns"]["payload"]["aps"],
+            serde_json::json!({
+                "alert": {
+                    "title": "title",
+                    "subtitle": "subtitle",
+                    "body": "body",
+                },
+                "badge": 3,
+                "sound": "default",
+                "thread-id": "thread_id",
+                "category": "category",
+                "mutable-content": 0,
+                "content-available": 0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_serialize_platform_overrides() {
+        let message = Message::new("title", "body");
+        let options = SendOptions {
+            apns_title: "apns title".to_string().into(),
+            android: AndroidConfig {
+                notification: AndroidNotification {
+                    body: "android body".to_string().into(),
+                    ..Default::default()
+                }
+                .into(),
+                ..Default::default()
+            }
+            .into(),
+            webpush: WebpushConfig {
+                notification: WebpushNotification {
+                    title: "webpush title".to_string().into(),
+                    ..Default::default()
+                }
+                .into(),
+                ..Default::default()
+            }
+            .into(),
+            ..Default::default()
+        };
+
+        let body = Body::<()>::new(
+            Target::Token("token".to_string()),
+            Some(&message),
+            &options,
+            None,
+        );
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(
+            actual["notification"],
+            serde_json::json!({ "title": "title", "body": "body" })
+        );
+        assert_eq!(
+            actual["apns"]["payload"]["aps"]["alert"],
+            serde_json::json!({ "title": "apns title", "body": "body" })
+        );
+        assert_eq!(
+            actual["android"]["notification"],
+            serde_json::json!({ "body": "android body" })
+        );
+        assert_eq!(
+            actual["webpush"]["notification"],
+            serde_json::json!({ "title": "webpush title" })
+        );
+    }
+
+    #[test]
+    fn test_serialize_localization() {
+        let message = Message::builder()
+            .title("title")
+            .body("body")
+            .title_loc("NEW_MESSAGE_TITLE", ["alice"])
+            .body_loc("NEW_MESSAGE_BODY", ["alice", "3"])
+            .build();
+        let options = SendOptions::default();
+
+        let body = Body::<()>::new(
+            Target::Token("token".to_string()),
+            Some(&message),
+            &options,
+            None,
+        );
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(
+            actual["notification"],
+            serde_json::json!({ "title": "title", "body": "body" })
+        );
+        assert_eq!(
+            actual["apns"]["payload"]["aps"]["alert"],
+            serde_json::json!({
+                "title": "title",
+                "body": "body",
+                "title-loc-key": "NEW_MESSAGE_TITLE",
+                "title-loc-args": ["alice"],
+                "loc-key": "NEW_MESSAGE_BODY",
+                "loc-args": ["alice", "3"],
+            })
+        );
+        assert_eq!(
+            actual["android"],
+            serde_json::json!({
+                "notification": {
+                    "title_loc_key": "NEW_MESSAGE_TITLE",
+                    "title_loc_args": ["alice"],
+                    "body_loc_key": "NEW_MESSAGE_BODY",
+                    "body_loc_args": ["alice", "3"],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_serialize_apns_custom_data() {
+        let options = SendOptions {
+            apns_custom_data: serde_json::json!({ "deep_link": "app://books/1" })
+                .as_object()
+                .cloned(),
+            ..Default::default()
+        };
+
+        let body = Body::<()>::new(Target::Token("token".to_string()), None, &options, None);
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(actual["apns"]["payload"]["deep_link"], "app://books/1");
+        assert!(actual["apns"]["payload"].get("aps").is_some());
+    }
+
+    #[test]
+    fn test_serialize_interruption_level() {
+        let options = SendOptions {
+            interruption_level: ApnsInterruptionLevel::TimeSensitive.into(),
+            relevance_score: 0.5.into(),
+            ..Default::default()
+        };
+        let body = Body::<()>::new(Target::Token("token".to_string()), None, &options, None);
+
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(
+            actual["apns"]["payload"]["aps"]["interruption-level"],
+            "time-sensitive"
+        );
+        assert_eq!(actual["apns"]["payload"]["aps"]["relevance-score"], 0.5);
+    }
+
+    #[test]
+    fn test_serialize_raw_aps() {
+        let aps = |raw_aps| {
+            let options = SendOptions {
+                badge: 1.into(),
+                sound: ApnsSound::Default.into(),
+                raw_aps,
+                ..Default::default()
+            };
+            let body = Body::<()>::new(Target::Token("token".to_string()), None, &options, None);
+
+            serde_json::to_value(&body).unwrap()["apns"]["payload"]["aps"].clone()
+        };
+
+        assert_eq!(
+            aps(serde_json::json!({ "interruption-level": "time-sensitive", "badge": 2 }).into()),
+            serde_json::json!({
+                "badge": 2,
+                "sound": "default",
+                "mutable-content": 0,
+                "content-available": 0,
+                "interruption-level": "time-sensitive",
+            })
+        );
+        assert_eq!(aps(serde_json::json!("verbatim").into()), "verbatim");
+    }
+
+    #[test]
+    fn test_serialize_collapse() {
+        let message = Message::builder()
+            .title("title")
+            .body("body")
+            .collapse("order-1")
+            .build();
+        let options = SendOptions::default();
+
+        let body = Body::<()>::new(
+            Target::Token("token".to_string()),
+            Some(&message),
+            &options,
+            None,
+        );
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(actual["android"]["collapse_key"], "order-1");
+        assert_eq!(actual["apns"]["headers"]["apns-collapse-id"], "order-1");
+        assert!(actual["notification"].get("collapse_id").is_none());
+
+        let options = SendOptions {
+            apns_collapse_id: Some("apns".to_string()),
+            android: AndroidConfig {
+                collapse_key: Some("android".to_string()),
+                ..Default::default()
+            }
+            .into(),
+            ..Default::default()
+        };
+        let body = Body::<()>::new(
+            Target::Token("token".to_string()),
+            Some(&message),
+            &options,
+            None,
+        );
+        let actual = serde_json::to_value(&body).unwrap();
+
+        assert_eq!(actual["android"]["collapse_key"], "android");
+        assert_eq!(actual["apns"]["headers"]["apns-collapse-id"], "apns");
+    }
+
+    #[tokio::test]
+    async fn test_send_with_correlation_id() {
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(
+            StatusCode::OK,
+            r#"{"name":"projects/project_id/messages/1"}"#,
+        );
+        transport.push_response(
+            StatusCode::OK,
+            r#"{"name":"projects/project_id/messages/2"}"#,
+        );
+
+        let fcm = fcm().with_transport(transport.clone());
+        let message = Message::builder()
+            .title("title")
+            .correlation_id("order-1")
+            .build();
+
+        let actual = fcm
+            .send(
+                "token",
+                &message,
+                SendOptions::default(),
+                Some([("key", "value")].into_iter().collect::<HashMap<_, _>>()),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(actual.correlation_id.as_deref(), Some("order-1"));
+
+        let actual = fcm
+            .send_to_topic("news", &message, SendOptions::default(), None::<()>)
+            .await
+            .unwrap();
+
+        assert_eq!(actual.correlation_id.as_deref(), Some("order-1"));
+
+        let requests = transport.requests();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+
+        assert_eq!(
+            body["message"]["data"],
+            serde_json::json!({ "key": "value", "__fcm_correlation_id": "order-1" })
+        );
+
+        let body: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+
+        assert_eq!(
+            body["message"]["data"],
+            serde_json::json!({ "__fcm_correlation_id": "order-1" })
+        );
+        // key of data would be sent twice
+        let actual = fcm
+            .send(
+                "token",
+                &message,
+                SendOptions::default(),
+                Some(serde_json::json!({ "__fcm_correlation_id": "order-2" })),
+            )
+            .await;
+
+        assert!(matches!(actual, Err(Error::InvalidData(_))));
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_serialize_critical_sound() {
+        let sound = ApnsSound::Critical {
+            name: "default".to_string(),
+            volume: 0.5,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&sound).unwrap(),
+            serde_json::json!({
+                "critical": 1,
+                "name": "default",
+                "volume": 0.5,
+            })
+        );
+    }
 
-impl Display for SendMessageErrorResponse {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.error.message)
+    #[test]
+    fn test_round_trip() {
+        let message = Message::builder()
+            .title("title")
+            .body("body")
+            .body_loc("NEW_MESSAGE_BODY", ["alice"])
+            .build();
+        let options = SendOptions {
+            priority: Priority::High.into(),
+            sound: ApnsSound::Critical {
+                name: "default".to_string(),
+                volume: 0.5,
+            }
+            .into(),
+            apns_expiration: (UNIX_EPOCH + Duration::from_secs(1_700_000_000)).into(),
+            android: AndroidConfig {
+                ttl: Duration::from_millis(3500).into(),
+                notification: AndroidNotification {
+                    light_settings: LightSettings {
+                        color: Color::default(),
+                        light_on_duration: Duration::from_secs(1),
+                        light_off_duration: Duration::from_secs(2),
+                    }
+                    .into(),
+                    ..Default::default()
+                }
+                .into(),
+                ..Default::default()
+            }
+            .into(),
+            ..Default::default()
+        };
+
+        let request_body = |message: &Message, options: &SendOptions| {
+            fcm()
+                .build_request_body(
+                    Target::Token("token".to_string()),
+                    Some(message),
+                    options,
+                    None::<&()>,
+                )
+                .unwrap()
+        };
+
+        let restored_message: Message =
+            serde_json::from_str(&serde_json::to_string(&message).unwrap()).unwrap();
+        let restored_options: SendOptions =
+            serde_json::from_str(&serde_json::to_string(&options).unwrap()).unwrap();
+
+        assert_eq!(restored_message.body_loc_args, ["alice"]);
+        assert_eq!(
+            request_body(&restored_message, &restored_options),
+            request_body(&message, &options)
+        );
+
+        let actual: Message = serde_json::from_str(r#"{"title":"title","body":"body"}"#).unwrap();
+        assert!(actual.image.is_none());
+        assert!(matches!(
+            serde_json::from_str::<ApnsSound>(r#""default""#).unwrap(),
+            ApnsSound::Default
+        ));
     }
-}
 
-#[derive(Debug, Serialize)]
-struct WrappedBody<'a, D>
-where
-    D: Serialize,
-{
-    message: Body<'a, D>,
-}
+    #[test]
+    fn test_message_builder() {
+        let message = Message::builder().title("title").body("body").build();
 
-#[derive(Debug, Clone, Copy)]
-pub enum Priority {
-    Low,
-    Normal,
-    High,
-}
+        assert_eq!(message.title, "title");
+        assert_eq!(message.body, "body");
+        assert!(serde_json::to_value(&message)
+            .unwrap()
+            .get("image")
+            .is_none());
+
+        let message = Message::builder()
+            .title("title")
+            .image("https://example.com/image.png")
+            .build();
+
+        assert_eq!(
+            serde_json::to_value(&message).unwrap()["image"],
+            "https://example.com/image.png"
+        );
+    }
 
-impl Default for Priority {
-    fn default() -> Self {
-        Self::Normal
+    #[test]
+    fn test_request_serialize_error() {
+        let options = SendOptions::default();
+        // json object keys must be strings
+        let data = [((1, 2), "value")].into_iter().collect::<HashMap<_, _>>();
+
+        let body = Body::new(
+            Target::Token("token".to_string()),
+            None,
+            &options,
+            Some(&data),
+        );
+        let body = WrappedBody::new(body, &options);
+
+        let r = FirebaseCloudMessaging::add_part("project_id", "token", &mut Vec::new(), body);
+
+        assert!(matches!(r, Err(Error::RequestSerialize(_))));
     }
-}
 
-#[derive(Debug, Default, Clone)]
-pub struct SendOptions {
-    pub content_available: Option<bool>,
+    fn fcm() -> FirebaseCloudMessaging {
+        FirebaseCloudMessaging::from_credential(mock::credential())
+    }
 
-    pub mutable_content: Option<bool>,
+    #[tokio::test]
+    async fn test_send_with_mock_transport() {
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(
+            StatusCode::OK,
+            r#"{"name":"projects/project_id/messages/1"}"#,
+        );
+        transport.push_response(
+            StatusCode::NOT_FOUND,
+            r#"{"error":{"code":404,"message":"Requested entity was not found.","status":"NOT_FOUND"}}"#,
+        );
+
+        let fcm = fcm().with_transport(transport.clone());
+
+        let actual = fcm
+            .send(
+                "token",
+                Message::new("title", "body"),
+                SendOptions::default(),
+                None::<()>,
+            )
+            .await
+            .unwrap();
 
-    pub priority: Option<Priority>,
-}
+        assert_eq!(actual.name, "projects/project_id/messages/1");
 
-impl SendOptions {
-    fn to_apns_payload(&self) -> WrappedApnsPayload {
-        let mutable_content = self.mutable_content.unwrap_or(false);
-        let content_available = self.content_available.unwrap_or(false);
-        let priority = match self.priority.unwrap_or(Priority::High) {
-            Priority::Low => 1,
-            Priority::Normal => 5,
-            Priority::High => 10,
-        };
+        // message can be borrowed as well as moved
+        let message = Message::new("title", "body");
+        let actual = fcm
+            .send_to_topic("news", &message, SendOptions::default(), None::<()>)
+            .await;
 
-        WrappedApnsPayload {
-            payload: Aps {
-                aps: ApnsPayload {
-                    mutable_content: if mutable_content { 1 } else { 0 }.into(),
-                    content_available: if content_available { 1 } else { 0 }.into(),
-                    priority: priority.into(),
-                },
-            },
+        assert!(matches!(
+            actual,
+            Err(Error::SendMessage { status: StatusCode::NOT_FOUND, response, .. }) if response.error.is_unregistered()
+        ));
+
+        let requests = transport.requests();
+
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[0].url,
+            "https://fcm.googleapis.com/v1/projects/project_id/messages:send"
+        );
+        assert_eq!(requests[0].bearer_token, None);
+
+        let body: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+
+        assert_eq!(body["message"]["topic"], "news");
+    }
+
+    #[tokio::test]
+    async fn test_send_many_cancellable() {
+        let transport = Arc::new(MockTransport::new());
+        for _ in 0..4 {
+            transport.push_response(
+                StatusCode::OK,
+                r#"{"name":"projects/project_id/messages/1"}"#,
+            );
         }
+
+        let cancel = CancellationToken::new();
+
+        let fcm = fcm().with_transport(transport.clone()).on_send(Box::new({
+            let cancel = cancel.clone();
+            move |_| cancel.cancel()
+        }));
+
+        let message = Message::new("title", "body");
+        let targets = ["a", "b", "c"].map(|x| (x, &message));
+
+        let actual = fcm
+            .send_many_cancellable(targets, SendOptions::default(), None::<()>, 1, &cancel)
+            .await;
+
+        assert_eq!(actual.len(), 1);
+        assert_eq!(actual[0].0, "a");
+        assert_eq!(transport.requests().len(), 1);
+
+        let actual = fcm
+            .send_many(targets, SendOptions::default(), None::<()>, 1)
+            .await;
+
+        assert_eq!(actual.len(), 3);
     }
-}
 
-#[derive(Debug, Serialize)]
-struct ApnsPayload {
-    #[serde(rename = "mutable-content", skip_serializing_if = "Option::is_none")]
-    mutable_content: Option<u8>,
-    #[serde(rename = "content-available", skip_serializing_if = "Option::is_none")]
-    content_available: Option<u8>,
-    #[serde(rename = "apns-priority", skip_serializing_if = "Option::is_none")]
-    priority: Option<u8>,
-}
+    #[tokio::test]
+    async fn test_with_oauth2_uses_http_client_of_builder() {
+        let (endpoint, requests) = mock::serve_recorded(vec![
+            mock::token_response("token"),
+            mock::token_response("token"),
+        ])
+        .await;
+
+        let builder =
+            || FirebaseCloudMessaging::builder(mock::credential()).with_user_agent("app/1.0");
+
+        builder()
+            .build()
+            .with_oauth2(mock::oauth2(&endpoint))
+            .access_token()
+            .await
+            .unwrap();
 
-#[derive(Debug, Serialize)]
-struct Aps {
-    aps: ApnsPayload,
-}
+        // own client of oauth2 is kept
+        builder()
+            .build()
+            .with_oauth2(mock::oauth2(&endpoint).with_client(reqwest::Client::new()))
+            .access_token()
+            .await
+            .unwrap();
 
-#[derive(Debug, Serialize)]
-struct WrappedApnsPayload {
-    payload: Aps,
-}
+        let requests = requests.lock();
 
-#[derive(Debug, Serialize)]
-struct Body<'a, D>
-where
-    D: Serialize,
-{
-    token: String,
-    notification: Cow<'a, Message>,
+        assert!(requests[0].to_lowercase().contains("user-agent: app/1.0"));
+        assert!(!requests[1].to_lowercase().contains("user-agent: app/1.0"));
+    }
 
-    apns: Option<WrappedApnsPayload>,
+    #[tokio::test]
+    async fn test_send_through_relay() {
+        let success = r#"{"name":"projects/project_id/messages/1"}"#.to_string();
+        let (relay, requests) = mock::serve_recorded(vec![
+            mock::token_response("token"),
+            (200, success.clone()),
+            (200, success),
+        ])
+        .await;
+
+        let fcm = fcm()
+            .with_base_url(format!("{relay}/"))
+            .with_oauth2(mock::oauth2(&relay));
+
+        let actual = fcm
+            .send(
+                "token",
+                Message::new("title", "body"),
+                SendOptions::default(),
+                None::<()>,
+            )
+            .await
+            .unwrap();
 
-    // TODO:
-    // android:,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    data: Option<&'a D>,
-}
+        assert_eq!(actual.message_id(), "1");
 
-impl<'a, D> Default for Body<'a, D>
-where
-    D: Serialize,
-{
-    fn default() -> Self {
-        Self {
-            token: "".to_string(),
-            notification: Default::default(),
-            apns: None,
-            data: None,
+        fcm.send_raw(serde_json::json!({ "topic": "news" }))
+            .await
+            .unwrap();
+
+        let requests = requests.lock();
+        let host = relay.trim_start_matches("http://");
+
+        assert!(requests[0].starts_with("POST /token "));
+        for req in &requests[1..] {
+            assert!(req.starts_with("POST /v1/projects/project_id/messages:send "));
+            assert!(req.to_lowercase().contains(&format!("host: {host}")));
+            assert!(req.to_lowercase().contains("authorization: bearer token"));
         }
+        assert!(requests[2].ends_with(r#"{"message":{"topic":"news"}}"#));
     }
-}
 
-#[derive(Debug, Serialize, Clone, Default)]
-pub struct Message {
-    pub title: String,
-    pub body: String,
-}
+    #[tokio::test]
+    async fn test_send_with_headers() {
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(
+            StatusCode::OK,
+            r#"{"name":"projects/project_id/messages/1"}"#,
+        );
+
+        let fcm = fcm().with_transport(transport.clone());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-goog-user-project", "billing".parse().unwrap());
+
+        fcm.send_with_headers(
+            "token",
+            Message::new("title", "body"),
+            SendOptions::default(),
+            None::<()>,
+            headers,
+        )
+        .await
+        .unwrap();
 
-impl Message {
-    pub fn new(title: impl Into<String>, body: impl Into<String>) -> Self {
-        Self {
-            title: title.into(),
-            body: body.into(),
-        }
+        let requests = transport.requests();
+
+        assert_eq!(requests[0].headers["x-goog-user-project"], "billing");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, "Bearer other".parse().unwrap());
+
+        let actual = fcm
+            .send_with_headers(
+                "token",
+                Message::new("title", "body"),
+                SendOptions::default(),
+                None::<()>,
+                headers,
+            )
+            .await;
+
+        assert!(matches!(actual, Err(Error::ReservedHeader(x)) if x == "authorization"));
+        assert_eq!(transport.requests().len(), 1);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::SendOptions;
+    #[tokio::test]
+    async fn test_with_base_url_and_project_id() {
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(
+            StatusCode::OK,
+            r#"{"name":"projects/project_id/messages/1"}"#,
+        );
+
+        let fcm = fcm()
+            .with_transport(transport.clone())
+            .with_base_url("http://127.0.0.1:9099/")
+            .with_project_id("other_project_id");
+
+        assert_eq!(fcm.project_id(), "other_project_id");
+
+        fcm.send_data_only(
+            "token",
+            SendOptions::default(),
+            HashMap::from([("key", "value")]),
+        )
+        .await
+        .unwrap();
 
-    use super::{
-        FirebaseCloudMessaging, Message, Priority, SendMessageError, SendMessageErrorResponse,
-        SendMessageSuccessResponse,
-    };
+        assert_eq!(
+            transport.requests()[0].url,
+            "http://127.0.0.1:9099/v1/projects/other_project_id/messages:send"
+        );
+    }
 
     #[tokio::test]
-    #[ignore]
-    async fn test_send_to_devices() {
-        let fcm = FirebaseCloudMessaging::from_credential_path("./firebase.credential.json");
+    async fn test_send_multicast_pruning() {
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(
+            StatusCode::OK,
+            r#"{"name":"projects/project_id/messages/1"}"#,
+        );
+        transport.push_response(
+            StatusCode::NOT_FOUND,
+            r#"{"error":{"code":404,"message":"Requested entity was not found.","status":"NOT_FOUND","details":[{"@type":"type.googleapis.com/google.firebase.fcm.v1.FcmError","errorCode":"UNREGISTERED"}]}}"#,
+        );
+        transport.push_response(
+            StatusCode::BAD_REQUEST,
+            r#"{"error":{"code":400,"message":"Invalid registration token","status":"INVALID_ARGUMENT"}}"#,
+        );
+
+        let fcm = fcm().with_transport(transport.clone());
+
+        let actual = fcm
+            .send_multicast_pruning(
+                ["a", "b", "c"],
+                Message::new("title", "body"),
+                SendOptions::default(),
+                None::<()>,
+            )
+            .await;
 
-        let registration_token = "";
+        assert_eq!(actual.success_count, 1);
+        assert_eq!(actual.failure_count, 2);
+        assert_eq!(actual.unregistered_tokens, ["b"]);
+        assert_eq!(actual.errors.len(), 1);
+        assert_eq!(actual.errors[0].0, "c");
+    }
+
+    #[tokio::test]
+    async fn test_on_send() {
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(
+            StatusCode::OK,
+            r#"{"name":"projects/project_id/messages/1"}"#,
+        );
+        transport.push_response(
+            StatusCode::NOT_FOUND,
+            r#"{"error":{"code":404,"message":"Requested entity was not found.","status":"NOT_FOUND"}}"#,
+        );
+
+        let events = Arc::new(parking_lot::Mutex::new(Vec::new()));
+
+        let fcm = fcm().with_transport(transport.clone()).on_send(Box::new({
+            let events = events.clone();
+            move |x: &SendEvent| events.lock().push((x.success, x.status))
+        }));
+
+        fcm.send_multicast(
+            ["a", "b"],
+            Message::new("title", "body"),
+            SendOptions::default(),
+            None::<()>,
+        )
+        .await;
+
+        assert_eq!(
+            *events.lock(),
+            [
+                (true, Some(StatusCode::OK)),
+                (false, Some(StatusCode::NOT_FOUND))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_on_send_with_retries() {
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"error":{"code":503,"message":"The service is currently unavailable.","status":"UNAVAILABLE"}}"#,
+        );
+        transport.push_response(
+            StatusCode::OK,
+            r#"{"name":"projects/project_id/messages/1"}"#,
+        );
+
+        let events = Arc::new(parking_lot::Mutex::new(Vec::new()));
+
+        let fcm = fcm()
+            .with_transport(transport.clone())
+            .with_retry_policy(RetryPolicy {
+                max_retries: 1,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(1),
+            })
+            .on_send(Box::new({
+                let events = events.clone();
+                move |x: &SendEvent| events.lock().push((x.success, x.status))
+            }));
+
+        fcm.send(
+            "token",
+            Message::new("title", "body"),
+            SendOptions::default(),
+            None::<()>,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(transport.requests().len(), 2);
+        assert_eq!(*events.lock(), [(true, Some(StatusCode::OK))]);
+    }
+
+    #[tokio::test]
+    async fn test_send_lenient() {
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(
+            StatusCode::NOT_FOUND,
+            r#"{"error":{"code":404,"message":"Requested entity was not found.","status":"NOT_FOUND"}}"#,
+        );
+        transport.push_response(
+            StatusCode::BAD_REQUEST,
+            r#"{"error":{"code":400,"message":"Invalid registration token","status":"INVALID_ARGUMENT"}}"#,
+        );
+
+        let fcm = fcm().with_transport(transport.clone());
+        let send = || {
+            fcm.send_lenient(
+                "token",
+                Message::new("title", "body"),
+                SendOptions::default(),
+                None::<()>,
+            )
+        };
+
+        assert_eq!(send().await.unwrap(), SendOutcome::TokenInvalid);
+        assert!(matches!(
+            send().await,
+            Err(Error::SendMessage {
+                status: StatusCode::BAD_REQUEST,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_send_raw() {
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(
+            StatusCode::OK,
+            r#"{"name":"projects/project_id/messages/1"}"#,
+        );
+
+        let fcm = fcm().with_transport(transport.clone());
+
+        let message = serde_json::json!({
+            "token": "token",
+            "android": { "direct_boot_ok": true },
+        });
+
+        let actual = fcm.send_raw(message.clone()).await.unwrap();
+
+        assert_eq!(actual.name, "projects/project_id/messages/1");
+
+        let body: serde_json::Value =
+            serde_json::from_slice(&transport.requests()[0].body).unwrap();
+
+        assert_eq!(body, serde_json::json!({ "message": message }));
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_policy() {
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(StatusCode::SERVICE_UNAVAILABLE, "");
+        transport.push_response(
+            StatusCode::OK,
+            r#"{"name":"projects/project_id/messages/1"}"#,
+        );
+
+        let fcm = fcm()
+            .with_transport(transport.clone())
+            .with_retry_policy(RetryPolicy {
+                max_retries: 1,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(1),
+            });
+
+        let actual = fcm
+            .send_data_only(
+                "token",
+                SendOptions::default(),
+                HashMap::from([("key", "value")]),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(actual.name, "projects/project_id/messages/1");
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_send_refreshes_token_on_unauthorized() {
+        let endpoint = mock::serve(vec![
+            mock::token_response("stale"),
+            mock::token_response("fresh"),
+        ])
+        .await;
+
+        let transport = Arc::new(MockTransport::with_auth());
+        let unauthorized = r#"{"error":{"code":401,"message":"Request had invalid authentication credentials.","status":"UNAUTHENTICATED"}}"#;
+        transport.push_response(StatusCode::UNAUTHORIZED, unauthorized);
+        transport.push_response(StatusCode::UNAUTHORIZED, unauthorized);
+
+        let fcm = fcm()
+            .with_transport(transport.clone())
+            .with_oauth2(mock::oauth2(&endpoint));
+
+        let actual = fcm
+            .send_data_only(
+                "token",
+                SendOptions::default(),
+                HashMap::from([("key", "value")]),
+            )
+            .await;
+
+        assert!(matches!(
+            actual,
+            Err(Error::SendMessage {
+                status: StatusCode::UNAUTHORIZED,
+                ..
+            })
+        ));
+
+        let tokens = transport
+            .requests()
+            .into_iter()
+            .map(|x| x.bearer_token.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(tokens, ["stale", "fresh"]);
+    }
+
+    #[tokio::test]
+    async fn test_send_succeeds_after_refresh_on_unauthorized() {
+        let endpoint = mock::serve(vec![
+            mock::token_response("stale"),
+            mock::token_response("fresh"),
+        ])
+        .await;
+
+        let transport = Arc::new(MockTransport::with_auth());
+        transport.push_response(
+            StatusCode::UNAUTHORIZED,
+            r#"{"error":{"code":401,"message":"Request had invalid authentication credentials.","status":"UNAUTHENTICATED"}}"#,
+        );
+        transport.push_response(
+            StatusCode::OK,
+            r#"{"name":"projects/project_id/messages/1"}"#,
+        );
+
+        let fcm = fcm()
+            .with_transport(transport.clone())
+            .with_oauth2(mock::oauth2(&endpoint));
+
+        let actual = fcm
+            .send_data_only(
+                "token",
+                SendOptions::default(),
+                HashMap::from([("key", "value")]),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(actual.message_id(), "1");
+
+        let tokens = transport
+            .requests()
+            .into_iter()
+            .map(|x| x.bearer_token.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(tokens, ["stale", "fresh"]);
+        assert_eq!(fcm.access_token().await.unwrap(), "fresh");
+    }
+
+    #[test]
+    fn test_clone_shares_state() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<FirebaseCloudMessaging>();
+
+        let a = fcm();
+        let b = a.clone();
+
+        assert!(Arc::ptr_eq(&a.oauth2, &b.oauth2));
+    }
+
+    #[test]
+    fn test_build_request_body() {
         let message = Message::new("title", "body");
+        let data = [("key", "value")].into_iter().collect::<HashMap<_, _>>();
+
+        let actual = fcm()
+            .build_request_body(
+                Target::Topic("news".to_string()),
+                Some(&message),
+                &SendOptions::default(),
+                Some(&data),
+            )
+            .unwrap();
 
-        #[derive(Debug, serde::Serialize)]
-        struct Data {
-            thumbnail: &'static str,
-            book_id: &'static str,
-        }
+        assert_eq!(actual["message"]["topic"], "news");
+        assert_eq!(actual["message"]["notification"]["title"], "title");
+        assert_eq!(actual["message"]["data"]["key"], "value");
+        assert_eq!(actual["message"]["apns"]["headers"]["apns-priority"], "10");
+        assert_eq!(
+            actual["message"]["apns"]["headers"]["apns-push-type"],
+            "alert"
+        );
+    }
 
-        let a = fcm
-            .send_to_devices(
-                [registration_token],
-                message,
-                SendOptions {
-                    mutable_content: true.into(),
-                    content_available: true.into(),
-                    priority: Priority::High.into(),
+    #[test]
+    fn test_build_silent_push() {
+        let options = SendOptions {
+            content_available: true.into(),
+            ..Default::default()
+        };
+
+        let actual = fcm()
+            .build_request_body(
+                Target::Token("token".to_string()),
+                None,
+                &options,
+                None::<&()>,
+            )
+            .unwrap();
+
+        assert!(actual["message"].get("notification").is_none());
+        assert!(actual["message"].get("data").is_none());
+        assert_eq!(
+            actual["message"]["apns"]["headers"],
+            serde_json::json!({
+                "apns-priority": "5",
+                "apns-push-type": "background",
+            })
+        );
+        assert_eq!(
+            actual["message"]["apns"]["payload"],
+            serde_json::json!({
+                "aps": {
+                    "mutable-content": 0,
+                    "content-available": 1,
                 },
-                Data {
-                    thumbnail: "https://file.madome.app/image/library/2699651/thumbnail",
-                    book_id: "2699651",
-                }
-                .into(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_data() {
+        let data = [("key", "value")].into_iter().collect::<HashMap<_, _>>();
+        assert!(FirebaseCloudMessaging::validate_data(Some(&data)).is_ok());
+
+        assert!(FirebaseCloudMessaging::validate_data(None::<&()>).is_ok());
+
+        let data = serde_json::json!({ "key": "value", "nested": { "key": "value" } });
+        assert!(matches!(
+            FirebaseCloudMessaging::validate_data(Some(&data)),
+            Err(Error::InvalidData(x)) if x.contains("nested")
+        ));
+
+        let data = serde_json::json!({ "count": 1 });
+        assert!(matches!(
+            FirebaseCloudMessaging::validate_data(Some(&data)),
+            Err(Error::InvalidData(_))
+        ));
+
+        assert!(matches!(
+            FirebaseCloudMessaging::validate_data(Some(&"value")),
+            Err(Error::InvalidData(_))
+        ));
+
+        let data = serde_json::json!({ "__fcm_correlation_id": "order-1" });
+        assert!(matches!(
+            FirebaseCloudMessaging::validate_data(Some(&data)),
+            Err(Error::InvalidData(x)) if x.contains("reserved")
+        ));
+    }
+
+    #[test]
+    fn test_validate_target() {
+        let validate =
+            |target| FirebaseCloudMessaging::validate_target(&target).map_err(|x| x.kind());
+
+        assert!(validate(Target::Token("fcm-token_1:APA91b".to_string())).is_ok());
+        assert!(validate(Target::Topic("".to_string())).is_ok());
+
+        for token in ["", " token", "token\n", "토큰", &"x".repeat(4097)] {
+            assert_eq!(
+                validate(Target::Token(token.to_string())),
+                Err(ErrorKind::InvalidToken)
+            );
+        }
+        assert_eq!(
+            validate(Target::NotificationKey("".to_string())),
+            Err(ErrorKind::InvalidToken)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_to_empty_token() {
+        let transport = Arc::new(MockTransport::new());
+        let fcm = fcm().with_transport(transport.clone());
+
+        let actual = fcm
+            .send(
+                "",
+                Message::new("title", "body"),
+                SendOptions::default(),
+                None::<()>,
             )
             .await;
 
-        println!("{a:?}")
+        assert!(matches!(actual, Err(Error::InvalidToken(_))));
+        assert!(transport.requests().is_empty());
+    }
+
+    #[test]
+    fn test_message_name() {
+        let actual = SendMessageSuccessResponse {
+            name: "projects/myproj/messages/0:1500415314455276%31bd1c9631bd1c96".to_string(),
+            correlation_id: None,
+        };
+
+        assert_eq!(actual.message_id(), "0:1500415314455276%31bd1c9631bd1c96");
+        assert_eq!(actual.project(), "myproj");
+
+        let actual = SendMessageSuccessResponse {
+            name: "unexpected".to_string(),
+            correlation_id: None,
+        };
+
+        assert_eq!(actual.message_id(), "unexpected");
+        assert_eq!(actual.project(), "");
+    }
+
+    #[test]
+    fn test_parse_error_keeps_body() {
+        let error = FirebaseCloudMessaging::parse_error(
+            "<html>Bad Gateway</html>",
+            StatusCode::BAD_GATEWAY,
+            None,
+        );
+
+        assert!(matches!(
+            error,
+            Error::ResponseDeserialize { body, .. } if body == "<html>Bad Gateway</html>"
+        ));
     }
 
     #[test]
@@ -456,6 +3486,7 @@ Vary: Referer
             Ok(SendMessageSuccessResponse {
                 name: "projects/35006771263/messages/0:1570471792141125%43c11b7043c11b70"
                     .to_string(),
+                correlation_id: None,
             }),
             Err(SendMessageErrorResponse {
                 error: SendMessageError {
@@ -463,11 +3494,13 @@ Vary: Referer
                     message: "The registration token is not a valid FCM registration token"
                         .to_string(),
                     status: "INVALID_ARGUMENT".to_string(),
+                    details: Vec::new(),
                 },
             }),
             Ok(SendMessageSuccessResponse {
                 name: "projects/35006771263/messages/0:1570471792141696%43c11b7043c11b70"
                     .to_string(),
+                correlation_id: None,
             }),
         ];
 
@@ -496,6 +3529,7 @@ Vary: Referer
 
         let expected = SendMessageSuccessResponse {
             name: "projects/35006771263/messages/0:1570471792141696%43c11b7043c11b70".to_string(),
+            correlation_id: None,
         };
 
         assert_eq!(actual, expected);
@@ -528,6 +3562,7 @@ Vary: Referer
                 code: 400,
                 message: "The registration token is not a valid FCM registration token".to_string(),
                 status: "INVALID_ARGUMENT".to_string(),
+                details: Vec::new(),
             },
         };
 
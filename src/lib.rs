@@ -1,12 +1,46 @@
+mod android;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod builder;
+mod data;
 mod error;
 mod fcm;
+mod message;
 mod oauth;
+mod registry;
+mod retry;
+mod topic;
+mod trace;
+mod transport;
+mod webpush;
 
-pub use error::Error;
+#[cfg(test)]
+mod mock;
+
+pub use android::{
+    deserialize_ttl, serialize_ttl, AndroidConfig, AndroidMessagePriority, AndroidNotification,
+    AndroidSound, Color, LightSettings, NotificationPriority,
+};
+pub use builder::FirebaseCloudMessagingBuilder;
+pub use data::DataPayload;
+pub use error::{Error, ErrorKind};
 pub use fcm::{
-    FirebaseCloudMessaging, Message, Priority, SendMessageError, SendMessageErrorResponse,
-    SendMessageSuccessResponse, SendOptions,
+    ApnsInterruptionLevel, ApnsSound, FcmErrorCode, FcmOptions, FieldViolation,
+    FirebaseCloudMessaging, Message, MessageBuilder, MulticastOutcome, Priority, SendEvent,
+    SendMessageError, SendMessageErrorDetail, SendMessageErrorResponse, SendMessageSuccessResponse,
+    SendOptions, SendOutcome, Target, CORRELATION_ID_KEY, FCM_SCOPE,
+};
+pub use message::{ApnsConfig, FcmMessage, FcmNotification};
+pub use oauth::{Credential, GoogleOAuth2, RefreshEvent, RefresherHandle, TokenClaims};
+pub use registry::FcmRegistry;
+pub use retry::RetryPolicy;
+pub use tokio_util::sync::CancellationToken;
+pub use topic::{
+    TokenInfo, TokenRelations, TopicManagementResponse, TopicManagementResult, TopicSubscription,
+};
+pub use transport::{FcmTransport, MockTransport, TransportRequest, TransportResponse};
+pub use webpush::{
+    WebpushConfig, WebpushFcmOptions, WebpushNotification, WebpushNotificationAction,
 };
-pub use oauth::{Credential, GoogleOAuth2};
 
 pub type Result<T> = std::result::Result<T, Error>;
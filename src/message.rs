@@ -0,0 +1,221 @@
+//! Typed model of whole v1 message resource, for building or inspecting messages field by field
+//!
+//! Reference: https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages
+
+use std::collections::HashMap;
+
+use http::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    trace, AndroidConfig, DataPayload, Error, FcmOptions, FirebaseCloudMessaging, Message,
+    SendMessageSuccessResponse, SendOptions, Target, WebpushConfig,
+};
+
+/// sent as is by [`FirebaseCloudMessaging::send_message`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FcmMessage {
+    #[serde(flatten)]
+    pub target: Target,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notification: Option<FcmNotification>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<DataPayload>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub android: Option<AndroidConfig>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub apns: Option<ApnsConfig>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webpush: Option<WebpushConfig>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fcm_options: Option<FcmOptions>,
+}
+
+/// top level notification, shown on every platform unless overridden
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FcmNotification {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+
+    /// publicly accessible url of image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+/// Reference: https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages#apnsconfig
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApnsConfig {
+    /// e.g. `apns-priority`, `apns-push-type`
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+
+    /// `aps` dictionary and custom keys, see [`FcmMessage::from_parts`] for building it from typed options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WrappedFcmMessage<T> {
+    message: T,
+}
+
+impl FcmMessage {
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            notification: None,
+            data: None,
+            android: None,
+            apns: None,
+            webpush: None,
+            fcm_options: None,
+        }
+    }
+
+    /// same message which [`FirebaseCloudMessaging::send`] would send, to be adjusted before [`FirebaseCloudMessaging::send_message`]
+    ///
+    /// `validate_only` of `options` is not part of message, so it is dropped
+    pub fn from_parts<D>(
+        target: Target,
+        message: Option<&Message>,
+        options: &SendOptions,
+        data: Option<&D>,
+    ) -> crate::Result<Self>
+    where
+        D: Serialize,
+    {
+        let body = FirebaseCloudMessaging::serialize_body(target, message, options, data)?;
+
+        serde_json::from_slice::<WrappedFcmMessage<Self>>(&body)
+            .map(|x| x.message)
+            .map_err(Error::MessageDeserialize)
+    }
+}
+
+impl FirebaseCloudMessaging {
+    /// sends fully specified `message`, for fields which convenience methods don't expose
+    pub async fn send_message(
+        &self,
+        message: &FcmMessage,
+    ) -> crate::Result<SendMessageSuccessResponse> {
+        Self::validate_target(&message.target)?;
+
+        let body = WrappedFcmMessage { message };
+        let body = serde_json::to_vec(&body).map_err(Error::RequestSerialize)?;
+
+        trace::traced!(
+            self.post_message(body, HeaderMap::new()),
+            "fcm.send",
+            project_id = %self.project_id(),
+            target = %trace::redact(&message.target),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use http::StatusCode;
+
+    use super::{FcmMessage, FcmNotification};
+    use crate::{
+        mock, DataPayload, FirebaseCloudMessaging, Message, MockTransport, SendOptions, Target,
+    };
+
+    #[test]
+    fn test_from_parts() {
+        let message = Message::builder()
+            .title("title")
+            .body("body")
+            .collapse("order-1")
+            .build();
+        let mut data = DataPayload::new();
+        data.insert("id", "1");
+
+        let actual = FcmMessage::from_parts(
+            Target::Topic("news".to_string()),
+            Some(&message),
+            &SendOptions::default(),
+            Some(&data),
+        )
+        .unwrap();
+
+        assert!(matches!(&actual.target, Target::Topic(x) if x == "news"));
+        assert_eq!(
+            actual.notification.as_ref().unwrap().title.as_deref(),
+            Some("title")
+        );
+        assert_eq!(actual.data, Some(data));
+        assert_eq!(
+            actual.android.as_ref().unwrap().collapse_key.as_deref(),
+            Some("order-1")
+        );
+
+        let apns = actual.apns.as_ref().unwrap();
+        assert_eq!(apns.headers["apns-collapse-id"], "order-1");
+        assert!(apns.payload.as_ref().unwrap().contains_key("aps"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = serde_json::json!({
+            "token": "token",
+            "notification": { "title": "title" },
+            "data": { "id": "1" },
+            "apns": { "headers": { "apns-priority": "5" } },
+        });
+
+        let message: FcmMessage = serde_json::from_value(json.clone()).unwrap();
+
+        assert!(matches!(&message.target, Target::Token(x) if x == "token"));
+        assert_eq!(serde_json::to_value(&message).unwrap(), json);
+    }
+
+    #[tokio::test]
+    async fn test_send_message() {
+        let transport = Arc::new(MockTransport::new());
+        transport.push_response(
+            StatusCode::OK,
+            r#"{"name":"projects/project_id/messages/1"}"#,
+        );
+
+        let fcm = FirebaseCloudMessaging::from_credential(mock::credential())
+            .with_transport(transport.clone());
+
+        let message = FcmMessage {
+            notification: FcmNotification {
+                title: Some("title".to_string()),
+                ..Default::default()
+            }
+            .into(),
+            ..FcmMessage::new(Target::Condition("'a' in topics".to_string()))
+        };
+
+        fcm.send_message(&message).await.unwrap();
+
+        let body: serde_json::Value =
+            serde_json::from_slice(&transport.requests()[0].body).unwrap();
+
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "message": {
+                    "condition": "'a' in topics",
+                    "notification": { "title": "title" },
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Minimal http server returning canned responses, and fixtures of fake service account, for tests
+
+use std::sync::Arc;
+
+use jsonwebtoken::Algorithm;
+use parking_lot::Mutex;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{Credential, GoogleOAuth2};
+
+/// fake service account of `project_id`, whose `private_key` is only usable as `HS256` secret
+pub fn credential() -> Credential {
+    Credential {
+        project_id: "project_id".to_string(),
+        private_key_id: "private_key_id".to_string(),
+        private_key: "private_key".to_string(),
+        client_email: "client@example.com".to_string(),
+    }
+}
+
+/// signs jwt of [`credential`] with `HS256`, and exchanges it at `{base_url}/token`
+pub fn oauth2(base_url: &str) -> GoogleOAuth2 {
+    GoogleOAuth2::from_credential(credential())
+        .with_algorithm(Algorithm::HS256)
+        .with_token_endpoint(format!("{base_url}/token"))
+}
+
+/// successful response of token endpoint, to be passed to [`serve`]
+pub fn token_response(access_token: &str) -> (u16, String) {
+    (
+        200,
+        format!(r#"{{"access_token":"{access_token}","expires_in":3599,"token_type":"Bearer"}}"#),
+    )
+}
+
+/// serves `responses` in order, one per connection, and returns base url of the server
+pub async fn serve(responses: Vec<(u16, String)>) -> String {
+    serve_recorded(responses).await.0
+}
+
+/// same as [`serve`], but also returns raw requests received so far
+pub async fn serve_recorded(responses: Vec<(u16, String)>) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let recorded = requests.clone();
+
+    tokio::spawn(async move {
+        for (status, body) in responses {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let req = read_request(&mut stream).await;
+            recorded.lock().push(req);
+
+            let res = format!(
+                "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+
+            stream.write_all(res.as_bytes()).await.unwrap();
+        }
+    });
+
+    (format!("http://{addr}"), requests)
+}
+
+async fn read_request(stream: &mut TcpStream) -> String {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+
+    loop {
+        let n = stream.read(&mut chunk).await.unwrap();
+        buf.extend_from_slice(&chunk[..n]);
+
+        let req = String::from_utf8_lossy(&buf);
+
+        if let Some((head, body)) = req.split_once("\r\n\r\n") {
+            let content_length = head
+                .lines()
+                .find_map(|x| {
+                    let (k, v) = x.split_once(':')?;
+                    k.eq_ignore_ascii_case("content-length")
+                        .then(|| v.trim().parse::<usize>().ok())?
+                })
+                .unwrap_or(0);
+
+            if body.len() >= content_length {
+                return req.into_owned();
+            }
+        }
+
+        if n == 0 {
+            return String::from_utf8_lossy(&buf).into_owned();
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Clients of multiple firebase projects, e.g. for multi-tenant server
+
+use std::{borrow::Borrow, collections::HashMap};
+
+use serde::Serialize;
+
+use crate::{
+    Credential, Error, FirebaseCloudMessaging, Message, SendMessageSuccessResponse, SendOptions,
+};
+
+/// clients keyed by their project id, each with its own oauth2 token cache
+#[derive(Clone, Default)]
+pub struct FcmRegistry {
+    clients: HashMap<String, FirebaseCloudMessaging>,
+}
+
+impl FcmRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// one client per credential, see [`FirebaseCloudMessaging::from_credential`]
+    pub fn from_credentials(creds: impl IntoIterator<Item = Credential>) -> Self {
+        creds
+            .into_iter()
+            .map(FirebaseCloudMessaging::from_credential)
+            .collect()
+    }
+
+    /// keyed by [`FirebaseCloudMessaging::project_id`], returns replaced client of same project, if any
+    pub fn insert(&mut self, fcm: FirebaseCloudMessaging) -> Option<FirebaseCloudMessaging> {
+        self.clients.insert(fcm.project_id().to_string(), fcm)
+    }
+
+    pub fn get(&self, project_id: &str) -> Option<&FirebaseCloudMessaging> {
+        self.clients.get(project_id)
+    }
+
+    pub fn remove(&mut self, project_id: &str) -> Option<FirebaseCloudMessaging> {
+        self.clients.remove(project_id)
+    }
+
+    pub fn project_ids(&self) -> impl Iterator<Item = &str> {
+        self.clients.keys().map(String::as_str)
+    }
+
+    /// same as [`FirebaseCloudMessaging::send`] with client of `project_id`
+    ///
+    /// returns [`Error::UnknownProject`] if there is no such client
+    pub async fn send<D>(
+        &self,
+        project_id: &str,
+        registration_token: impl Into<String>,
+        message: impl Borrow<Message>,
+        options: SendOptions,
+        data: Option<D>,
+    ) -> crate::Result<SendMessageSuccessResponse>
+    where
+        D: Serialize,
+    {
+        let fcm = self
+            .get(project_id)
+            .ok_or_else(|| Error::UnknownProject(project_id.to_string()))?;
+
+        fcm.send(registration_token, message, options, data).await
+    }
+}
+
+impl FromIterator<FirebaseCloudMessaging> for FcmRegistry {
+    fn from_iter<T: IntoIterator<Item = FirebaseCloudMessaging>>(iter: T) -> Self {
+        let mut registry = Self::new();
+
+        for fcm in iter {
+            registry.insert(fcm);
+        }
+
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use http::StatusCode;
+
+    use super::FcmRegistry;
+    use crate::{mock, Error, FirebaseCloudMessaging, Message, MockTransport, SendOptions};
+
+    fn fcm(project_id: &str, transport: Arc<MockTransport>) -> FirebaseCloudMessaging {
+        FirebaseCloudMessaging::from_credential(mock::credential())
+            .with_project_id(project_id)
+            .with_transport(transport)
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let a = Arc::new(MockTransport::new());
+        let b = Arc::new(MockTransport::new());
+        b.push_response(StatusCode::OK, r#"{"name":"projects/b/messages/1"}"#);
+
+        let registry: FcmRegistry = [fcm("a", a.clone()), fcm("b", b.clone())]
+            .into_iter()
+            .collect();
+
+        let actual = registry
+            .send(
+                "b",
+                "token",
+                Message::new("title", "body"),
+                SendOptions::default(),
+                None::<()>,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(actual.project(), "b");
+        assert!(a.requests().is_empty());
+        assert_eq!(b.requests().len(), 1);
+
+        let actual = registry
+            .send(
+                "c",
+                "token",
+                Message::new("title", "body"),
+                SendOptions::default(),
+                None::<()>,
+            )
+            .await;
+
+        assert!(matches!(actual, Err(Error::UnknownProject(x)) if x == "c"));
+    }
+}
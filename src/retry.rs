@@ -0,0 +1,181 @@
+//! Retry policy for transient send failures
+//!
+//! Reference: https://firebase.google.com/docs/cloud-messaging/scale-fcm#errors
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use http::{header, HeaderMap, StatusCode};
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// how many times a request is retried after the first attempt
+    pub max_retries: u32,
+
+    /// delay before the first retry, doubled on every retry
+    pub base_delay: Duration,
+
+    /// upper bound of delay between retries
+    ///
+    /// if `Retry-After` of response is longer, it is not retried, and the error has `retry_after` instead
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// `429 Too Many Requests`, `500 Internal Server Error`, `503 Service Unavailable`
+    pub(crate) fn is_retryable(status: StatusCode) -> bool {
+        matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS
+                | StatusCode::INTERNAL_SERVER_ERROR
+                | StatusCode::SERVICE_UNAVAILABLE
+        )
+    }
+
+    /// returns delay before retrying `attempt`th (zero-based) retry, or `None` if it should not be retried
+    pub(crate) fn delay(
+        &self,
+        attempt: u32,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> Option<Duration> {
+        if attempt >= self.max_retries || !Self::is_retryable(status) {
+            return None;
+        }
+
+        match parse_retry_after(headers) {
+            Some(delay) if delay > self.max_delay => None,
+            Some(delay) => Some(delay),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+
+    /// exponential backoff with jitter, between half and full of `base_delay * 2^attempt`
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2_u32.saturating_pow(attempt))
+            .min(self.max_delay);
+
+        let half = delay / 2;
+        let jitter = half.mul_f64(random());
+
+        half + jitter
+    }
+}
+
+/// `Retry-After` in seconds or http date
+pub(crate) fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let x = headers.get(header::RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = x.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(x).ok()?;
+    let delay = (date.with_timezone(&Utc) - Utc::now())
+        .to_std()
+        .unwrap_or_default();
+
+    Some(delay)
+}
+
+/// 0.0..=1.0
+fn random() -> f64 {
+    let x = RandomState::new().build_hasher().finish();
+
+    x as f64 / u64::MAX as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use http::{header, HeaderMap, HeaderValue, StatusCode};
+
+    use super::{parse_retry_after, RetryPolicy};
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(3),
+        };
+        let headers = HeaderMap::new();
+
+        let delay = policy
+            .delay(0, StatusCode::SERVICE_UNAVAILABLE, &headers)
+            .unwrap();
+        assert!(delay >= Duration::from_millis(500) && delay <= Duration::from_secs(1));
+
+        let delay = policy
+            .delay(2, StatusCode::TOO_MANY_REQUESTS, &headers)
+            .unwrap();
+        assert!(delay >= Duration::from_millis(1500) && delay <= Duration::from_secs(3));
+
+        assert_eq!(
+            policy.delay(3, StatusCode::SERVICE_UNAVAILABLE, &headers),
+            None
+        );
+        assert_eq!(policy.delay(0, StatusCode::BAD_REQUEST, &headers), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RETRY_AFTER, HeaderValue::from_static("2"));
+
+        assert_eq!(
+            policy.delay(0, StatusCode::TOO_MANY_REQUESTS, &headers),
+            Some(Duration::from_secs(2))
+        );
+
+        // longer than max_delay, so caller gets the error with `retry_after` instead of waiting
+        headers.insert(header::RETRY_AFTER, HeaderValue::from_static("86400"));
+        assert_eq!(
+            policy.delay(0, StatusCode::TOO_MANY_REQUESTS, &headers),
+            None
+        );
+
+        let date = (chrono::Utc::now() + chrono::Duration::days(1)).to_rfc2822();
+        headers.insert(header::RETRY_AFTER, HeaderValue::from_str(&date).unwrap());
+        assert_eq!(
+            policy.delay(0, StatusCode::SERVICE_UNAVAILABLE, &headers),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let mut headers = HeaderMap::new();
+
+        assert_eq!(parse_retry_after(&headers), None);
+
+        headers.insert(header::RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(parse_retry_after(&headers), Some(Duration::from_secs(120)));
+
+        headers.insert(
+            header::RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(parse_retry_after(&headers), Some(Duration::ZERO));
+
+        let date = (chrono::Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        headers.insert(header::RETRY_AFTER, HeaderValue::from_str(&date).unwrap());
+
+        let delay = parse_retry_after(&headers).unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+    }
+}
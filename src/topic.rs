@@ -0,0 +1,351 @@
+//! Server side topic management and token info via the Instance ID API
+
+use std::collections::HashMap;
+
+use http::{header, Method, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{Error, FirebaseCloudMessaging, Target};
+
+pub(crate) const IID_BASE_URL: &str = "https://iid.googleapis.com";
+
+/// maximum number of tokens of a single topic management request
+const MAX_TOPIC_TOKENS: usize = 1000;
+
+#[derive(Debug, Serialize)]
+struct TopicManagementRequest<'a> {
+    to: String,
+    registration_tokens: &'a [&'a str],
+}
+
+/// `results` are in the same order as requested tokens
+#[derive(Debug, Deserialize)]
+#[cfg_attr(test, derive(Eq, PartialEq))]
+pub struct TopicManagementResponse {
+    #[serde(default)]
+    pub results: Vec<TopicManagementResult>,
+}
+
+#[derive(Debug, Deserialize)]
+#[cfg_attr(test, derive(Eq, PartialEq))]
+pub struct TopicManagementResult {
+    /// e.g. `NOT_FOUND`, `INVALID_ARGUMENT`, `None` if succeeded
+    pub error: Option<String>,
+}
+
+impl TopicManagementResult {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// metadata of registration token, returned by [`FirebaseCloudMessaging::get_token_info`]
+#[derive(Debug, Deserialize)]
+#[cfg_attr(test, derive(Eq, PartialEq))]
+#[serde(rename_all = "camelCase")]
+pub struct TokenInfo {
+    /// package name or bundle id
+    pub application: Option<String>,
+    /// e.g. `ANDROID`, `IOS`, `WEBPUSH`
+    pub platform: Option<String>,
+    /// sender id (project number) which the token is authorized for
+    pub authorized_entity: Option<String>,
+    #[serde(default)]
+    pub rel: TokenRelations,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[cfg_attr(test, derive(Eq, PartialEq))]
+pub struct TokenRelations {
+    /// subscribed topic names, without `/topics/` prefix
+    #[serde(default)]
+    pub topics: HashMap<String, TopicSubscription>,
+}
+
+#[derive(Debug, Deserialize)]
+#[cfg_attr(test, derive(Eq, PartialEq))]
+#[serde(rename_all = "camelCase")]
+pub struct TopicSubscription {
+    /// e.g. `2015-07-30`
+    pub add_date: Option<String>,
+}
+
+impl FirebaseCloudMessaging {
+    /// returns app, platform and topic subscriptions of `token`
+    ///
+    /// returns [`Error::InvalidToken`] if `token` is malformed, see [`FirebaseCloudMessaging::send`]
+    pub async fn get_token_info(&self, token: &str) -> crate::Result<TokenInfo> {
+        Self::validate_target(&Target::Token(token.to_string()))?;
+
+        let oauth2_token = self.oauth2.access_token().await?;
+
+        let url = format!("{}/iid/info/{}", self.iid_base_url, path_segment(token));
+
+        let res = self
+            .client
+            .request(Method::GET, url)
+            .query(&[("details", "true")])
+            .header("access_token_auth", "true")
+            .bearer_auth(oauth2_token)
+            .send()
+            .await?;
+
+        Self::parse_iid_response(res).await
+    }
+
+    /// subscribes up to 1000 tokens to `topic`, which may be given with or without `/topics/` prefix
+    ///
+    /// returns [`Error::TooManyTokens`] without requesting if there are more tokens
+    pub async fn subscribe_to_topic(
+        &self,
+        tokens: &[&str],
+        topic: &str,
+    ) -> crate::Result<TopicManagementResponse> {
+        self.manage_topic("batchAdd", tokens, topic).await
+    }
+
+    /// unsubscribes up to 1000 tokens from `topic`, which may be given with or without `/topics/` prefix
+    ///
+    /// returns [`Error::TooManyTokens`] without requesting if there are more tokens
+    pub async fn unsubscribe_from_topic(
+        &self,
+        tokens: &[&str],
+        topic: &str,
+    ) -> crate::Result<TopicManagementResponse> {
+        self.manage_topic("batchRemove", tokens, topic).await
+    }
+
+    async fn manage_topic(
+        &self,
+        operation: &str,
+        tokens: &[&str],
+        topic: &str,
+    ) -> crate::Result<TopicManagementResponse> {
+        if tokens.len() > MAX_TOPIC_TOKENS {
+            return Err(Error::TooManyTokens(tokens.len()));
+        }
+
+        let body = TopicManagementRequest {
+            to: topic_path(topic),
+            registration_tokens: tokens,
+        };
+        let body = serde_json::to_vec(&body).map_err(Error::RequestSerialize)?;
+
+        let oauth2_token = self.oauth2.access_token().await?;
+
+        let res = self
+            .client
+            .request(
+                Method::POST,
+                format!("{}/iid/v1:{operation}", self.iid_base_url),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            // required to authorize iid api with oauth2 token instead of server key
+            .header("access_token_auth", "true")
+            .bearer_auth(oauth2_token)
+            .body(body)
+            .send()
+            .await?;
+
+        Self::parse_iid_response(res).await
+    }
+
+    async fn parse_iid_response<T>(res: reqwest::Response) -> crate::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let status = res.status();
+        let res = res.text().await?;
+
+        if status != StatusCode::OK {
+            return Err(Error::InstanceId { status, body: res });
+        }
+
+        serde_json::from_str(&res).map_err(|source| Error::ResponseDeserialize {
+            source,
+            body: res.clone(),
+        })
+    }
+}
+
+fn topic_path(topic: &str) -> String {
+    match topic.starts_with("/topics/") {
+        true => topic.to_string(),
+        false => format!("/topics/{topic}"),
+    }
+}
+
+/// percent-encodes `s` except unreserved characters, so that it stays a single path segment
+fn path_segment(s: &str) -> String {
+    s.bytes()
+        .map(|x| match x {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' => {
+                (x as char).to_string()
+            }
+            _ => format!("%{x:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{
+        path_segment, topic_path, TokenInfo, TokenRelations, TopicManagementRequest,
+        TopicManagementResponse, TopicManagementResult, TopicSubscription,
+    };
+    use crate::{mock, Error, FirebaseCloudMessaging};
+
+    fn fcm(base_url: &str) -> FirebaseCloudMessaging {
+        FirebaseCloudMessaging::from_credential(mock::credential())
+            .with_iid_base_url(format!("{base_url}/"))
+            .with_oauth2(mock::oauth2(base_url))
+    }
+
+    #[test]
+    fn test_serialize_request() {
+        let tokens = ["a", "b"];
+        let body = TopicManagementRequest {
+            to: topic_path("news"),
+            registration_tokens: &tokens,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&body).unwrap(),
+            r#"{"to":"/topics/news","registration_tokens":["a","b"]}"#
+        );
+        assert_eq!(topic_path("/topics/news"), "/topics/news");
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let actual: TopicManagementResponse =
+            serde_json::from_str(r#"{"results":[{},{"error":"NOT_FOUND"}]}"#).unwrap();
+
+        assert_eq!(
+            actual,
+            TopicManagementResponse {
+                results: vec![
+                    TopicManagementResult { error: None },
+                    TopicManagementResult {
+                        error: Some("NOT_FOUND".to_string())
+                    },
+                ]
+            }
+        );
+        assert!(actual.results[0].is_success());
+        assert!(!actual.results[1].is_success());
+    }
+
+    #[test]
+    fn test_parse_token_info() {
+        let actual: TokenInfo = serde_json::from_str(
+            r#"{
+                "application": "com.iid.example",
+                "authorizedEntity": "123456782354",
+                "platform": "Android",
+                "attestStatus": "ROOTED",
+                "appSigner": "1a2bc3d4e5",
+                "connectionType": "WIFI",
+                "connectDate": "2015-05-12",
+                "rel": {
+                    "topics": {
+                        "topicname1": { "addDate": "2015-07-30" }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            actual,
+            TokenInfo {
+                application: Some("com.iid.example".to_string()),
+                platform: Some("Android".to_string()),
+                authorized_entity: Some("123456782354".to_string()),
+                rel: TokenRelations {
+                    topics: HashMap::from([(
+                        "topicname1".to_string(),
+                        TopicSubscription {
+                            add_date: Some("2015-07-30".to_string())
+                        }
+                    )])
+                },
+            }
+        );
+
+        let actual: TokenInfo = serde_json::from_str(r#"{"platform":"IOS"}"#).unwrap();
+        assert!(actual.rel.topics.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_manage_topic() {
+        let (base_url, requests) = mock::serve_recorded(vec![
+            mock::token_response("token"),
+            (200, r#"{"results":[{},{"error":"NOT_FOUND"}]}"#.to_string()),
+            (200, r#"{"results":[{}]}"#.to_string()),
+        ])
+        .await;
+
+        let fcm = fcm(&base_url);
+
+        let actual = fcm.subscribe_to_topic(&["a", "b"], "news").await.unwrap();
+        assert!(!actual.results[1].is_success());
+
+        let actual = fcm.unsubscribe_from_topic(&["a"], "news").await.unwrap();
+        assert!(actual.results[0].is_success());
+
+        let requests = requests.lock();
+
+        assert!(requests[1].starts_with("POST /iid/v1:batchAdd "));
+        assert!(requests[1].contains("access_token_auth: true"));
+        assert!(requests[1]
+            .to_lowercase()
+            .contains("authorization: bearer token"));
+        assert!(requests[1].ends_with(r#"{"to":"/topics/news","registration_tokens":["a","b"]}"#));
+        assert!(requests[2].starts_with("POST /iid/v1:batchRemove "));
+    }
+
+    #[tokio::test]
+    async fn test_manage_topic_with_too_many_tokens() {
+        let fcm = fcm("http://127.0.0.1:1");
+
+        let tokens = vec!["token"; 1001];
+
+        assert!(matches!(
+            fcm.subscribe_to_topic(&tokens, "news").await,
+            Err(Error::TooManyTokens(1001))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_token_info() {
+        let (base_url, requests) = mock::serve_recorded(vec![
+            mock::token_response("token"),
+            (200, r#"{"platform":"IOS"}"#.to_string()),
+            (
+                404,
+                r#"{"error":"No information found about this instance id."}"#.to_string(),
+            ),
+        ])
+        .await;
+
+        let fcm = fcm(&base_url);
+
+        let actual = fcm.get_token_info("a/b?c").await.unwrap();
+        assert_eq!(actual.platform.as_deref(), Some("IOS"));
+
+        assert!(matches!(
+            fcm.get_token_info("token").await,
+            Err(Error::InstanceId { status, .. }) if status == http::StatusCode::NOT_FOUND
+        ));
+        assert!(matches!(
+            fcm.get_token_info("").await,
+            Err(Error::InvalidToken(_))
+        ));
+
+        assert!(requests.lock()[1].starts_with("GET /iid/info/a%2Fb%3Fc?details=true "));
+        assert_eq!(path_segment("ab-_:12"), "ab-_:12");
+    }
+}
@@ -0,0 +1,103 @@
+//! Optional tracing instrumentation, enabled by `tracing` feature
+
+use http::StatusCode;
+
+/// awaits `$fut` in a span named `$name` with `status` and `latency_ms` fields, plus given fields
+///
+/// without `tracing` feature, just awaits `$fut` and given fields are not evaluated
+macro_rules! traced {
+    ($fut:expr, $name:literal $(, $($field:tt)*)?) => {{
+        #[cfg(feature = "tracing")]
+        let res = $crate::trace::timed(
+            tracing::info_span!(
+                $name,
+                status = tracing::field::Empty,
+                latency_ms = tracing::field::Empty
+                $(, $($field)*)?
+            ),
+            $fut,
+        )
+        .await;
+
+        #[cfg(not(feature = "tracing"))]
+        let res = $fut.await;
+
+        res
+    }};
+}
+
+pub(crate) use traced;
+
+#[cfg(feature = "tracing")]
+pub(crate) async fn timed<F>(span: tracing::Span, fut: F) -> F::Output
+where
+    F: std::future::Future,
+{
+    use tracing::Instrument;
+
+    let start = std::time::Instant::now();
+    let res = fut.instrument(span.clone()).await;
+
+    span.record("latency_ms", start.elapsed().as_millis() as u64);
+
+    res
+}
+
+/// records `status` on span of [`traced`]
+pub(crate) fn record_status(status: StatusCode) {
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("status", status.as_u16());
+
+    #[cfg(not(feature = "tracing"))]
+    let _ = status;
+}
+
+/// registration tokens are credentials of devices, so only their prefix is recorded
+///
+/// short or malformed tokens are not recorded at all, as prefix would reveal most of them
+#[cfg(feature = "tracing")]
+pub(crate) fn redact(target: &crate::Target) -> String {
+    match target {
+        crate::Target::Token(token) | crate::Target::NotificationKey(token) => {
+            match token.get(..8) {
+                Some(prefix) if token.len() >= 32 => format!("token:{prefix}…"),
+                _ => "token:<redacted>".to_string(),
+            }
+        }
+        crate::Target::Topic(topic) => format!("topic:{topic}"),
+        crate::Target::Condition(condition) => format!("condition:{condition}"),
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate::Target;
+
+    use super::redact;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            redact(&Target::Token("abcdefgh".repeat(4))),
+            "token:abcdefgh…"
+        );
+        assert_eq!(
+            redact(&Target::Token("abc".to_string())),
+            "token:<redacted>"
+        );
+        assert_eq!(
+            redact(&Target::Token("abcdefghijklmnop".to_string())),
+            "token:<redacted>"
+        );
+        // 8th byte is inside of multibyte character
+        assert_eq!(
+            redact(&Target::Token(format!("a{}", "日本語".repeat(10)))),
+            "token:<redacted>"
+        );
+        assert_eq!(
+            redact(&Target::NotificationKey("key".to_string())),
+            "token:<redacted>"
+        );
+        assert_eq!(redact(&Target::Topic("news".to_string())), "topic:news");
+    }
+}
@@ -0,0 +1,132 @@
+//! Transport of `messages:send` requests, which can be replaced for testing
+
+use std::collections::VecDeque;
+
+use futures_util::future::BoxFuture;
+use http::{header, HeaderMap, Method, StatusCode};
+use parking_lot::Mutex;
+use reqwest::Client;
+
+#[derive(Debug, Clone)]
+pub struct TransportRequest {
+    pub url: String,
+    /// `None` if transport does not require auth, see [`FcmTransport::requires_auth`]
+    pub bearer_token: Option<String>,
+    /// additional headers, e.g. given by [`crate::FirebaseCloudMessaging::send_with_headers`]
+    pub headers: HeaderMap,
+    /// json body
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TransportResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+pub trait FcmTransport: Send + Sync {
+    fn post(&self, request: TransportRequest) -> BoxFuture<'_, crate::Result<TransportResponse>>;
+
+    /// if false, oauth2 token is not fetched and requests are sent without it
+    fn requires_auth(&self) -> bool {
+        true
+    }
+}
+
+impl FcmTransport for Client {
+    fn post(&self, request: TransportRequest) -> BoxFuture<'_, crate::Result<TransportResponse>> {
+        Box::pin(async move {
+            let mut req = self
+                .request(Method::POST, &request.url)
+                .header(header::CONTENT_TYPE, "application/json")
+                .headers(request.headers)
+                .body(request.body);
+
+            if let Some(bearer_token) = request.bearer_token {
+                req = req.bearer_auth(bearer_token);
+            }
+
+            let res = req.send().await?;
+
+            let status = res.status();
+            let headers = res.headers().clone();
+            let body = res.text().await?;
+
+            Ok(TransportResponse {
+                status,
+                headers,
+                body,
+            })
+        })
+    }
+}
+
+/// test double which returns canned responses in order, and records requests
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use fcm_sdk::MockTransport;
+/// use http::StatusCode;
+///
+/// let transport = Arc::new(MockTransport::new());
+/// transport.push_response(StatusCode::OK, r#"{"name":"projects/p/messages/1"}"#);
+///
+/// // fcm.with_transport(transport.clone());
+/// ```
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    responses: Mutex<VecDeque<TransportResponse>>,
+    requests: Mutex<Vec<TransportRequest>>,
+    requires_auth: bool,
+}
+
+impl MockTransport {
+    /// requests are sent without oauth2 token
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// oauth2 token is fetched and sent as `bearer_token`, e.g. to test refresh on `401`
+    pub fn with_auth() -> Self {
+        Self {
+            requires_auth: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn push_response(&self, status: StatusCode, body: impl Into<String>) {
+        self.responses.lock().push_back(TransportResponse {
+            status,
+            headers: HeaderMap::new(),
+            body: body.into(),
+        });
+    }
+
+    /// requests received so far
+    pub fn requests(&self) -> Vec<TransportRequest> {
+        self.requests.lock().clone()
+    }
+}
+
+impl FcmTransport for MockTransport {
+    /// # Panics
+    ///
+    /// panics if there is no canned response left
+    fn post(&self, request: TransportRequest) -> BoxFuture<'_, crate::Result<TransportResponse>> {
+        self.requests.lock().push(request);
+
+        let res = self
+            .responses
+            .lock()
+            .pop_front()
+            .expect("no canned response left in MockTransport");
+
+        Box::pin(async move { Ok(res) })
+    }
+
+    fn requires_auth(&self) -> bool {
+        self.requires_auth
+    }
+}
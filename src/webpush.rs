@@ -0,0 +1,79 @@
+//! Webpush specific options of message
+//!
+//! Reference: https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages#webpushconfig
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebpushConfig {
+    /// e.g. `TTL`, `Urgency`
+    ///
+    /// Reference: https://tools.ietf.org/html/rfc8030#section-5
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+
+    /// overrides data of message
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub data: HashMap<String, String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification: Option<WebpushNotification>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fcm_options: Option<WebpushFcmOptions>,
+}
+
+/// Reference: https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages#webpushfcmoptions
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebpushFcmOptions {
+    /// opened when user clicks notification, must be https
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+/// Reference: https://developer.mozilla.org/en-US/docs/Web/API/Notification/Notification
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct WebpushNotification {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+
+    /// icon url
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+
+    /// image url
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+
+    /// badge image url
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub badge: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_interaction: Option<bool>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<WebpushNotificationAction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebpushNotificationAction {
+    pub action: String,
+
+    pub title: String,
+
+    /// icon url
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+}